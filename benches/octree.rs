@@ -0,0 +1,162 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use grid_tree::{Level, NodePtr, VisitCommand};
+use octree_experiment::{
+    octree::Octree,
+    refine::{refine, RefineSettings},
+    subdivision::{ChebyshevBox, LodObserver},
+};
+
+fn observer(position: Vec3, detail: i32) -> LodObserver {
+    LodObserver {
+        position,
+        detail,
+        min_level: 0,
+        projection_scale: None,
+    }
+}
+
+fn settings(height: Level) -> RefineSettings {
+    RefineSettings {
+        height,
+        merge_margin: 1,
+        balance: None,
+    }
+}
+
+fn refined_tree(height: Level, observers: &[LodObserver]) -> Octree {
+    let mut tree = Octree::new(height);
+    refine(&mut tree, observers, &ChebyshevBox, settings(height));
+    tree
+}
+
+/// The positions of a target flying a loop through the middle of the root, one per update, like
+/// the orbit of the headless example.
+fn target_path(height: Level, steps: usize) -> Vec<Vec3> {
+    let root_length = (1 << (height - 1)) as f32;
+    let center = Vec3::splat(root_length / 2.0);
+    (0..steps)
+        .map(|step| {
+            let angle = step as f32 / steps as f32 * TAU;
+            let offset = Vec3::new(angle.cos(), (2.0 * angle).sin() / 4.0, angle.sin());
+            center + offset * root_length / 3.0
+        })
+        .collect()
+}
+
+/// Builds a tree from scratch, which fills every root with `fill_tree_from_root`.
+fn full_rebuild(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full_rebuild");
+    for height in [6, 8, 10, 12] {
+        for detail in [1, 2, 4] {
+            let center = Vec3::splat((1 << (height - 1)) as f32 / 2.0);
+            let observers = [observer(center, detail)];
+            group.bench_with_input(
+                BenchmarkId::new(format!("height_{height}"), detail),
+                &observers,
+                |b, observers| b.iter(|| refined_tree(height, observers)),
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Follows a target along its path, refining the same tree after each step.
+fn incremental_update(c: &mut Criterion) {
+    const STEPS: usize = 256;
+
+    let mut group = c.benchmark_group("incremental_update");
+    group.throughput(Throughput::Elements(STEPS as u64));
+    for height in [8, 10, 12] {
+        let path = target_path(height, STEPS);
+        let start = [observer(path[0], 1)];
+        group.bench_with_input(BenchmarkId::from_parameter(height), &path, |b, path| {
+            b.iter_batched(
+                || refined_tree(height, &start),
+                |mut tree| {
+                    for &position in path {
+                        refine(
+                            &mut tree,
+                            &[observer(position, 1)],
+                            &ChebyshevBox,
+                            settings(height),
+                        );
+                    }
+                    tree
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// Walks the whole tree with `visit_tree_depth_first`, directly and through the queries built on
+/// it.
+fn traversal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("traversal");
+    for height in [8, 10, 12] {
+        let center = Vec3::splat((1 << (height - 1)) as f32 / 2.0);
+        let tree = refined_tree(height, &[observer(center, 2)]);
+        let roots: Vec<_> = tree
+            .iter_roots()
+            .map(|(root_key, root_node)| {
+                (NodePtr::new(root_key.level, root_node.self_ptr), *root_key)
+            })
+            .collect();
+
+        group.bench_function(BenchmarkId::new("visit_tree_depth_first", height), |b| {
+            b.iter(|| {
+                let mut visited = 0;
+                for &(root_ptr, root_key) in &roots {
+                    tree.visit_tree_depth_first(root_ptr, root_key.coordinates, 0, |_, _| {
+                        visited += 1;
+                        VisitCommand::Continue
+                    });
+                }
+                visited
+            })
+        });
+        group.bench_function(BenchmarkId::new("split_nodes", height), |b| {
+            b.iter(|| tree.split_nodes())
+        });
+        group.bench_function(BenchmarkId::new("leaves_overlapping_root", height), |b| {
+            b.iter(|| tree.leaves_overlapping(roots[0].1))
+        });
+    }
+    group.finish();
+}
+
+/// Refines a tree from scratch around targets spread over neighboring roots.
+fn multi_target(c: &mut Criterion) {
+    const HEIGHT: Level = 10;
+
+    let root_length = (1 << (HEIGHT - 1)) as f32;
+    let mut group = c.benchmark_group("multi_target");
+    for targets in [1, 2, 4, 8] {
+        let observers: Vec<_> = (0..targets)
+            .map(|i| {
+                let angle = i as f32 / targets as f32 * TAU;
+                let position = Vec3::new(angle.cos(), 0.1, angle.sin()) * root_length;
+                observer(position, 1)
+            })
+            .collect();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(targets),
+            &observers,
+            |b, observers| b.iter(|| refined_tree(HEIGHT, observers)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    full_rebuild,
+    incremental_update,
+    traversal,
+    multi_target
+);
+criterion_main!(benches);
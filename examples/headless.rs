@@ -0,0 +1,51 @@
+//! Runs the LOD logic without window or renderer and writes per-tick statistics as CSV.
+//!
+//! ```sh
+//! cargo run --release --example headless -- examples/headless.ron stats.csv
+//! ```
+//!
+//! Without arguments, the default [`HeadlessConfig`] is used and the statistics are printed.
+
+use std::{env, fs, process};
+
+use octree_experiment::headless::{run_headless, HeadlessConfig, TickStats};
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    let config = match args.next() {
+        Some(path) => {
+            let text = fs::read_to_string(&path).unwrap_or_else(|error| {
+                eprintln!("Can't read {path}: {error}");
+                process::exit(1);
+            });
+            ron::from_str::<HeadlessConfig>(&text).unwrap_or_else(|error| {
+                eprintln!("Invalid config {path}: {error}");
+                process::exit(1);
+            })
+        }
+        None => HeadlessConfig::default(),
+    };
+
+    let stats = run_headless(&config).unwrap_or_else(|error| {
+        eprintln!("Invalid config: {error}");
+        process::exit(1);
+    });
+
+    let mut csv = String::from(TickStats::CSV_HEADER);
+    csv.push('\n');
+    for tick in &stats {
+        tick.write_csv_row(&mut csv);
+    }
+
+    match args.next() {
+        Some(path) => {
+            if let Err(error) = fs::write(&path, csv) {
+                eprintln!("Can't write {path}: {error}");
+                process::exit(1);
+            }
+            println!("Wrote {} ticks to {path}", stats.len());
+        }
+        None => print!("{csv}"),
+    }
+}
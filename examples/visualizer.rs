@@ -0,0 +1,193 @@
+//! Orbits a target around the middle of the root node and shows the resulting LOD terrain.
+//!
+//! Move with the mouse and WASD, press Escape to release the cursor and Tab to cycle through the
+//! subdivision strategies.
+
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    window::{Cursor, CursorGrabMode},
+};
+use bevy_inspector_egui::quick::WorldInspectorPlugin;
+use octree_experiment::{
+    subdivision::{ChebyshevBox, EuclideanSphere, MinLevel, ScreenSpaceError, SizeRatio},
+    voxel::Terrain,
+    ChunkGenerator, ChunkMaterial, LodStrategy, OctreeLodDebugPlugin, OctreeLodPlugin,
+    OctreeLodSet, OctreeSettings, Target,
+};
+use smooth_bevy_cameras::{
+    controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
+    LookTransformPlugin,
+};
+
+fn main() {
+    App::new()
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                cursor: {
+                    let mut cursor = Cursor::default();
+                    cursor.visible = false;
+                    cursor.grab_mode = CursorGrabMode::Locked;
+                    cursor
+                },
+                ..default()
+            }),
+            ..default()
+        }))
+        .add_plugin(WorldInspectorPlugin::new())
+        .add_plugin(LookTransformPlugin)
+        .add_plugin(FpsCameraPlugin::default())
+        .add_plugin(OctreeLodPlugin::<()>::default())
+        .add_plugin(OctreeLodDebugPlugin::<()>::default())
+        .add_startup_system(init)
+        .add_system(toggle_cursor_and_camera)
+        .add_system(cycle_strategy)
+        .add_system(update_camera_speed.run_if(resource_changed::<OctreeSettings>()))
+        .add_system(move_target.before(OctreeLodSet::Chunks))
+        .run()
+}
+
+fn init(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    settings: Res<OctreeSettings>,
+) {
+    info!("Starting up!");
+
+    let root_length = settings.root_length();
+
+    info!(?root_length);
+
+    commands.insert_resource(ChunkGenerator(Arc::new(Terrain::for_height(
+        settings.height(),
+    ))));
+    commands.insert_resource(ChunkMaterial(materials.add(StandardMaterial {
+        base_color: Color::rgb(0.4, 0.6, 0.3),
+        perceptual_roughness: 0.9,
+        ..default()
+    })));
+
+    commands.spawn(DirectionalLightBundle {
+        transform: Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, -1.0, 0.5, 0.0)),
+        ..default()
+    });
+
+    commands
+        .spawn(Camera3dBundle::default())
+        .insert(FpsCameraBundle::new(
+            FpsCameraController {
+                translate_sensitivity: root_length as f32 / 2.0, // we should take 2s to travel the root node
+                ..default()
+            },
+            Vec3::splat(10.0),
+            Vec3::ZERO,
+            Vec3::Y,
+        ));
+
+    let sphere = meshes.add(
+        Mesh::try_from(shape::Icosphere {
+            radius: 0.5,
+            subdivisions: 5,
+        })
+        .unwrap(),
+    );
+    commands.spawn(PbrBundle {
+        mesh: sphere.clone(),
+        material: materials.add(Color::RED.into()),
+        transform: Transform::IDENTITY,
+        ..default()
+    });
+
+    commands.spawn((
+        Target,
+        PbrBundle {
+            mesh: sphere,
+            material: materials.add(StandardMaterial {
+                base_color: Color::GREEN,
+                unlit: true,
+                ..default()
+            }),
+            transform: Transform::from_translation(Vec3::new(
+                (root_length / 2) as f32,
+                (root_length / 2) as f32,
+                0.0,
+            )),
+            ..default()
+        },
+    ));
+}
+
+fn move_target(
+    settings: Res<OctreeSettings>,
+    mut target_query: Query<&mut Transform, With<Target>>,
+) {
+    for mut transform in &mut target_query {
+        transform.translate_around(
+            Vec3::splat((settings.root_length() / 2) as f32),
+            Quat::from_euler(EulerRot::XYZ, 0.005, 0.005, 0.005),
+        )
+    }
+}
+
+fn update_camera_speed(
+    settings: Res<OctreeSettings>,
+    mut cameras: Query<&mut FpsCameraController>,
+) {
+    for mut camera in &mut cameras {
+        // we should take 2s to travel the root node
+        camera.translate_sensitivity = settings.root_length() as f32 / 2.0;
+    }
+}
+
+/// Switches to the next built-in subdivision strategy when pressing Tab.
+fn cycle_strategy(
+    keys: Res<Input<KeyCode>>,
+    mut strategy: ResMut<LodStrategy>,
+    mut index: Local<usize>,
+) {
+    if !keys.just_pressed(KeyCode::Tab) {
+        return;
+    }
+
+    let strategies: [(&str, LodStrategy); 5] = [
+        ("Chebyshev box", LodStrategy(Arc::new(ChebyshevBox))),
+        ("Euclidean sphere", LodStrategy(Arc::new(EuclideanSphere))),
+        ("size ratio", LodStrategy(Arc::new(SizeRatio::default()))),
+        (
+            "Chebyshev box down to level 2",
+            LodStrategy(Arc::new(MinLevel {
+                min_level: 2,
+                inner: ChebyshevBox,
+            })),
+        ),
+        (
+            "screen-space error",
+            LodStrategy(Arc::new(ScreenSpaceError::default())),
+        ),
+    ];
+
+    *index = (*index + 1) % strategies.len();
+    let (name, next) = strategies.into_iter().nth(*index).unwrap();
+    info!("Using the {name} subdivision strategy");
+    *strategy = next;
+}
+
+fn toggle_cursor_and_camera(
+    keys: Res<Input<KeyCode>>,
+    mut windows: Query<&mut Window>,
+    mut cameras: Query<&mut FpsCameraController>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        let mut window = windows.single_mut();
+        window.cursor.visible = !window.cursor.visible;
+        window.cursor.grab_mode = match window.cursor.grab_mode {
+            CursorGrabMode::None => CursorGrabMode::Locked,
+            CursorGrabMode::Confined | CursorGrabMode::Locked => CursorGrabMode::None,
+        };
+
+        let mut camera = cameras.single_mut();
+        camera.enabled = !camera.enabled;
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::{prelude::*, utils::HashSet};
+use grid_tree::{Level, NodeKey};
+use serde::Deserialize;
+
+use crate::octree::{find_leaf, NodePayload, Octree};
+
+/// Which leaves are neighbors when balancing the tree.
+#[derive(Reflect, FromReflect, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Adjacency {
+    /// Leaves sharing a face.
+    #[default]
+    Face,
+    /// Leaves sharing a face or an edge.
+    Edge,
+    /// Leaves sharing a face, an edge or a corner.
+    Corner,
+}
+
+impl Adjacency {
+    /// Points just outside of `key` in each direction it has neighbors in. Each neighbor touching
+    /// the node and at least as big as it contains one of them.
+    fn neighbor_probes(self, key: NodeKey<IVec3>) -> impl Iterator<Item = Vec3> {
+        let max_axes = match self {
+            Adjacency::Face => 1,
+            Adjacency::Edge => 2,
+            Adjacency::Corner => 3,
+        };
+        let half_size = (1 << key.level) as f32 / 2.0;
+        let center = (key.coordinates * (1 << key.level)).as_vec3() + half_size;
+
+        (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .filter(move |direction| {
+                let axes = direction.abs().dot(IVec3::ONE);
+                axes > 0 && axes <= max_axes
+            })
+            .map(move |direction| center + direction.as_vec3() * (half_size + 0.5))
+    }
+}
+
+/// Two adjacent leaves whose levels differ by more than one.
+#[derive(Clone, Copy, Debug)]
+pub struct BalanceViolation {
+    pub leaf: NodeKey<IVec3>,
+    pub coarse_neighbor: NodeKey<IVec3>,
+}
+
+/// The interior nodes of the tree refined by `can_subdivide` from `root_keys`, with the leaves
+/// that are more than one level coarser than one of their neighbors refined further. Feeding them
+/// to [`Octree::update`] gives a balanced tree in a single pass, without merging and splitting the
+/// same nodes again every frame.
+pub fn balanced_interior(
+    root_keys: &HashSet<NodeKey<IVec3>>,
+    root_level: Level,
+    mut can_subdivide: impl FnMut(NodeKey<IVec3>) -> bool,
+    adjacency: Adjacency,
+) -> HashSet<NodeKey<IVec3>> {
+    let mut interior = HashSet::default();
+    let mut leaves = HashSet::default();
+
+    let mut stack: Vec<_> = root_keys.iter().copied().collect();
+    while let Some(key) = stack.pop() {
+        if key.level > 0 && can_subdivide(key) {
+            interior.insert(key);
+            stack.extend(children(key));
+        } else {
+            leaves.insert(key);
+        }
+    }
+
+    let mut to_check: Vec<_> = leaves.iter().copied().collect();
+    while let Some(leaf) = to_check.pop() {
+        if !leaves.contains(&leaf) {
+            continue;
+        }
+
+        for probe in adjacency.neighbor_probes(leaf) {
+            let Some(neighbor) = find_leaf(&leaves, root_level, probe) else {
+                continue;
+            };
+            if neighbor.level <= leaf.level + 1 {
+                continue;
+            }
+
+            leaves.remove(&neighbor);
+            interior.insert(neighbor);
+            for child in children(neighbor) {
+                leaves.insert(child);
+                to_check.push(child);
+            }
+            // The child next to the leaf may still be too coarse.
+            to_check.push(leaf);
+        }
+    }
+
+    interior
+}
+
+/// Every pair of adjacent leaves of the tree whose levels differ by more than one.
+pub fn find_balance_violations<T: NodePayload>(
+    tree: &Octree<T>,
+    adjacency: Adjacency,
+) -> Vec<BalanceViolation> {
+    tree.leaves()
+        .flat_map(|leaf| {
+            adjacency
+                .neighbor_probes(leaf)
+                .filter_map(|probe| tree.leaf_at(probe))
+                .filter(move |neighbor| neighbor.level > leaf.level + 1)
+                .map(move |coarse_neighbor| BalanceViolation {
+                    leaf,
+                    coarse_neighbor,
+                })
+        })
+        .collect()
+}
+
+fn children(key: NodeKey<IVec3>) -> impl Iterator<Item = NodeKey<IVec3>> {
+    (0..8).map(move |i| {
+        let offset = IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1);
+        NodeKey::new(key.level - 1, (key.coordinates << 1) + offset)
+    })
+}
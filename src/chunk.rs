@@ -0,0 +1,121 @@
+use bevy::{prelude::*, utils::HashMap};
+use grid_tree::{Level, NodeKey};
+
+use crate::{
+    load::ChunkState,
+    mesh::RemeshChunk,
+    octree::{
+        LodNode, LodNodeAdded, LodNodeMerged, LodNodeRemoved, LodNodeSplit, NodePayload, Octree,
+    },
+};
+
+/// Put on the entity of each leaf of the octree. The entity's [`Transform`] is at `min`.
+#[derive(Component, Reflect, Clone, Copy, Debug, Default)]
+#[reflect(Component)]
+pub struct ChunkKey {
+    pub level: Level,
+    pub coordinates: IVec3,
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl ChunkKey {
+    pub fn new(node: LodNode) -> Self {
+        Self {
+            level: node.level,
+            coordinates: node.key.coordinates,
+            min: node.min,
+            max: node.max,
+        }
+    }
+
+    pub fn key(&self) -> NodeKey<IVec3> {
+        NodeKey::new(self.level, self.coordinates)
+    }
+}
+
+/// Put on the chunks of nodes that stopped being leaves, which are kept around until the chunks
+/// replacing them are [`ChunkState::Ready`].
+#[derive(Component)]
+pub struct RetiringChunk;
+
+/// The chunk entities, by node key.
+#[derive(Resource, Default)]
+pub struct Chunks {
+    /// The entity of each leaf of the octree.
+    pub leaves: HashMap<NodeKey<IVec3>, Entity>,
+    /// Entities of former leaves that still cover a region the new leaves haven't loaded yet.
+    pub retiring: HashMap<NodeKey<IVec3>, Entity>,
+}
+
+impl Chunks {
+    /// Whether `entity` is still the chunk of `key`, as a leaf or retiring. Chunks that are not
+    /// may have been despawned earlier in the frame, so no command must be queued for them.
+    pub fn contains(&self, key: NodeKey<IVec3>, entity: Entity) -> bool {
+        self.leaves.get(&key) == Some(&entity) || self.retiring.get(&key) == Some(&entity)
+    }
+}
+
+/// Spawns an entity for each new leaf and retires the ones of the leaves that were split or
+/// removed. Chunks that weren't ready yet are despawned right away, which cancels their jobs.
+#[allow(clippy::too_many_arguments)]
+pub fn update_chunks<T: NodePayload>(
+    mut commands: Commands,
+    tree: Res<Octree<T>>,
+    mut chunks: ResMut<Chunks>,
+    states: Query<&ChunkState>,
+    mut added_events: EventReader<LodNodeAdded>,
+    mut removed_events: EventReader<LodNodeRemoved>,
+    mut split_events: EventReader<LodNodeSplit>,
+    mut merged_events: EventReader<LodNodeMerged>,
+) {
+    // Retire first, a rebuilt tree may reuse the keys of the nodes it dropped.
+    let gone = removed_events
+        .iter()
+        .map(|event| event.key)
+        .chain(split_events.iter().map(|event| event.key));
+    for key in gone {
+        let Some(entity) = chunks.leaves.remove(&key) else {
+            continue;
+        };
+
+        if matches!(states.get(entity), Ok(ChunkState::Ready)) {
+            commands.entity(entity).insert(RetiringChunk);
+            chunks.retiring.insert(key, entity);
+        } else {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    let new = added_events
+        .iter()
+        .map(|event| event.0)
+        .chain(merged_events.iter().map(|event| event.0));
+    for node in new {
+        // Several fixed updates can run in one frame, so a key can be added or merged twice.
+        if !tree.is_leaf(node.key) || chunks.leaves.contains_key(&node.key) {
+            continue;
+        }
+
+        // A chunk that comes back before it was despawned is still up to date, except for its
+        // seams.
+        if let Some(entity) = chunks.retiring.remove(&node.key) {
+            commands
+                .entity(entity)
+                .remove::<RetiringChunk>()
+                .insert(RemeshChunk);
+            chunks.leaves.insert(node.key, entity);
+            continue;
+        }
+
+        let entity = commands
+            .spawn((
+                ChunkKey::new(node),
+                ChunkState::default(),
+                Name::new(format!("Chunk {} {}", node.level, node.key.coordinates)),
+                SpatialBundle::from_transform(Transform::from_translation(node.min.as_vec3())),
+            ))
+            .id();
+        chunks.leaves.insert(node.key, entity);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+use bevy::{
+    ecs::event::ManualEventReader, prelude::*, time::TimeUpdateStrategy,
+    transform::TransformPlugin, utils::Instant,
+};
+use grid_tree::Level;
+use serde::Deserialize;
+
+use crate::{
+    balance::Adjacency,
+    chunk::Chunks,
+    load::ChunkState,
+    lod::{OctreeSettings, Target},
+    octree::{LodNodeAdded, LodNodeMerged, LodNodeRemoved, LodNodeSplit, Octree},
+    subdivision::LodRangeError,
+    voxel::{ChunkGenerator, Terrain},
+    OctreeLodPlugin, OctreeLodSet,
+};
+
+/// A run without window or renderer, usually read from a RON file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HeadlessConfig {
+    /// Number of app updates to run.
+    pub ticks: u32,
+    /// Simulated time between two ticks, in seconds. The tree is refined once per tick.
+    pub timestep: f32,
+    pub height: Level,
+    pub detail: i32,
+    pub merge_margin: i32,
+    pub balance: Option<Adjacency>,
+    /// Refine the tree on another thread, in which case its changes show up a few ticks later and
+    /// the run isn't deterministic anymore.
+    pub background_update: bool,
+    /// Sample the terrain of the visualizer into the chunks.
+    pub generate_chunks: bool,
+    pub targets: Vec<TargetConfig>,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        let settings = OctreeSettings::default();
+        let middle = (settings.root_length() / 2) as f32;
+        Self {
+            ticks: 600,
+            timestep: settings.lod_timestep,
+            height: settings.height,
+            detail: settings.detail,
+            merge_margin: settings.merge_margin,
+            balance: settings.balance,
+            background_update: false,
+            generate_chunks: false,
+            targets: vec![TargetConfig {
+                position: [middle, middle, 0.0],
+                orbit: Some(OrbitConfig {
+                    center: [middle; 3],
+                    angles: [0.005; 3],
+                }),
+            }],
+        }
+    }
+}
+
+impl HeadlessConfig {
+    fn settings(&self) -> OctreeSettings {
+        OctreeSettings {
+            height: self.height,
+            detail: self.detail,
+            merge_margin: self.merge_margin,
+            balance: self.balance,
+            lod_timestep: self.timestep,
+            background_update: self.background_update,
+            ..default()
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TargetConfig {
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub orbit: Option<OrbitConfig>,
+}
+
+/// Turns a target around `center` each tick, by the XYZ Euler `angles` in radians.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct OrbitConfig {
+    pub center: [f32; 3],
+    pub angles: [f32; 3],
+}
+
+#[derive(Component, Clone, Copy, Debug)]
+struct Orbit {
+    center: Vec3,
+    rotation: Quat,
+}
+
+fn orbit_targets(mut targets: Query<(&mut Transform, &Orbit)>) {
+    for (mut transform, orbit) in &mut targets {
+        transform.translate_around(orbit.center, orbit.rotation);
+    }
+}
+
+/// What happened during one tick of a headless run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TickStats {
+    pub tick: u32,
+    /// Wall time of the app update.
+    pub update_time: Duration,
+    pub leaves: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub split: usize,
+    pub merged: usize,
+    /// Chunks of the current leaves that are [`ChunkState::Ready`].
+    pub ready_chunks: usize,
+    /// Chunks of former leaves still waiting for the new ones to load.
+    pub retiring_chunks: usize,
+}
+
+impl TickStats {
+    pub const CSV_HEADER: &'static str =
+        "tick,update_us,leaves,added,removed,split,merged,ready_chunks,retiring_chunks";
+
+    pub fn write_csv_row(&self, out: &mut String) {
+        // Writing to a `String` can't fail.
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            self.tick,
+            self.update_time.as_micros(),
+            self.leaves,
+            self.added,
+            self.removed,
+            self.split,
+            self.merged,
+            self.ready_chunks,
+            self.retiring_chunks
+        );
+    }
+}
+
+/// Counts the node events sent since the last call.
+#[derive(Default)]
+struct EventCounter {
+    added: ManualEventReader<LodNodeAdded>,
+    removed: ManualEventReader<LodNodeRemoved>,
+    split: ManualEventReader<LodNodeSplit>,
+    merged: ManualEventReader<LodNodeMerged>,
+}
+
+impl EventCounter {
+    fn count(&mut self, world: &World, stats: &mut TickStats) {
+        stats.added = self.added.iter(world.resource()).count();
+        stats.removed = self.removed.iter(world.resource()).count();
+        stats.split = self.split.iter(world.resource()).count();
+        stats.merged = self.merged.iter(world.resource()).count();
+    }
+}
+
+/// Builds an app with [`MinimalPlugins`] and [`OctreeLodPlugin`] and runs it for the configured
+/// number of ticks, with simulated time so that every tick refines the tree once. Fails if the tree
+/// can't be refined with the configured height and detail.
+pub fn run_headless(config: &HeadlessConfig) -> Result<Vec<TickStats>, LodRangeError> {
+    let settings = config.settings();
+    settings.check_lod_range()?;
+    let timestep = Duration::from_secs_f32(settings.lod_timestep.max(0.001));
+    let height = settings.height();
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .insert_resource(settings)
+        .add_plugin(OctreeLodPlugin::<()>::default())
+        .insert_resource(FixedTime::new(timestep))
+        .add_system(orbit_targets.before(OctreeLodSet::Chunks));
+
+    if config.generate_chunks {
+        app.insert_resource(ChunkGenerator(Arc::new(Terrain::for_height(height))));
+    }
+
+    for target in &config.targets {
+        let mut entity = app.world.spawn((
+            Target,
+            TransformBundle::from_transform(Transform::from_translation(Vec3::from_array(
+                target.position,
+            ))),
+        ));
+        if let Some(orbit) = target.orbit {
+            let [x, y, z] = orbit.angles;
+            entity.insert(Orbit {
+                center: Vec3::from_array(orbit.center),
+                rotation: Quat::from_euler(EulerRot::XYZ, x, y, z),
+            });
+        }
+    }
+
+    let mut events = EventCounter::default();
+    let mut chunk_states = app.world.query::<&ChunkState>();
+    let epoch = Instant::now();
+    let stats = (0..config.ticks)
+        .map(|tick| {
+            // The first update only starts the clock, each of the next ones advances it by exactly
+            // one timestep.
+            app.insert_resource(TimeUpdateStrategy::ManualInstant(epoch + timestep * tick));
+
+            let start = Instant::now();
+            app.update();
+
+            let mut stats = TickStats {
+                tick,
+                update_time: start.elapsed(),
+                leaves: app.world.resource::<Octree>().leaves().count(),
+                ..default()
+            };
+            events.count(&app.world, &mut stats);
+
+            let chunks = app.world.resource::<Chunks>();
+            stats.retiring_chunks = chunks.retiring.len();
+            stats.ready_chunks = chunks
+                .leaves
+                .values()
+                .filter(|&&entity| {
+                    matches!(chunk_states.get(&app.world, entity), Ok(ChunkState::Ready))
+                })
+                .count();
+
+            stats
+        })
+        .collect();
+    Ok(stats)
+}
@@ -0,0 +1,158 @@
+//! LOD for Bevy apps, built on an octree refined around [`Target`] entities.
+//!
+//! Add [`OctreeLodPlugin`] for the tree and its chunk entities, and [`OctreeLodDebugPlugin`] to
+//! draw the tree. Chunks are generated once the app inserts a [`ChunkGenerator`], and meshed if
+//! it inserts a [`ChunkMaterial`] too.
+
+pub mod balance;
+pub mod chunk;
+pub mod headless;
+pub mod load;
+pub mod lod;
+pub mod mesh;
+pub mod octree;
+pub mod octree_i64;
+pub mod refine;
+pub mod render;
+pub mod subdivision;
+pub mod voxel;
+
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLinesPlugin;
+
+pub use chunk::ChunkKey;
+pub use lod::{LodChangeRate, LodSettings, OctreeSettings, Target, MAX_OCTREE_HEIGHT};
+pub use mesh::ChunkMaterial;
+pub use octree::{
+    LodNode, LodNodeAdded, LodNodeMerged, LodNodeRemoved, LodNodeSplit, NodePayload, Octree,
+};
+pub use octree_i64::MAX_OCTREE_HEIGHT_I64;
+pub use subdivision::{LodRangeError, LodStrategy, SubdivisionStrategy};
+pub use voxel::ChunkGenerator;
+
+use balance::Adjacency;
+use chunk::{update_chunks, Chunks};
+use load::{poll_chunk_jobs, retire_chunks, start_chunk_jobs, LoadBudget, LoadFocus, LoadQueue};
+use lod::{
+    measure_lod_change_rate, update_load_focus, update_lod_timestep, update_octree,
+    validate_balance,
+};
+use mesh::invalidate_seams;
+use refine::LodWorker;
+use render::render;
+
+/// The systems added by the plugins of this crate, in the order they run.
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OctreeLodSet {
+    /// Refines the tree around the targets and sends the node events. Runs in
+    /// [`CoreSchedule::FixedUpdate`], every [`OctreeSettings::lod_timestep`].
+    UpdateTree,
+    /// Spawns and retires the chunk entities of the leaves.
+    Chunks,
+    /// Generates and meshes the chunks. Only runs once the app inserted a [`ChunkGenerator`], and
+    /// only meshes them if there is a [`ChunkMaterial`] as well.
+    Load,
+    /// Draws the tree, when enabled in [`OctreeSettings`].
+    Render,
+}
+
+/// Maintains an [`Octree`] holding a `T` in each node around the [`Target`] entities, with one
+/// [`ChunkKey`] entity per leaf. The tree is created with the height of the [`OctreeSettings`]
+/// already in the app, if any.
+pub struct OctreeLodPlugin<T: NodePayload = ()> {
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T: NodePayload> Default for OctreeLodPlugin<T> {
+    fn default() -> Self {
+        Self {
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<T: NodePayload> Plugin for OctreeLodPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OctreeSettings>();
+        let height = app.world.resource::<OctreeSettings>().height();
+
+        app.register_type::<OctreeSettings>()
+            .register_type::<Adjacency>()
+            .register_type::<Target>()
+            .register_type::<LodSettings>()
+            .register_type::<ChunkKey>()
+            .register_type::<LoadBudget>()
+            .register_type::<LodChangeRate>()
+            .insert_resource(Octree::<T>::new(height))
+            .init_resource::<LodWorker>()
+            .init_resource::<LodStrategy>()
+            .init_resource::<Chunks>()
+            .init_resource::<LoadBudget>()
+            .init_resource::<LoadFocus>()
+            .init_resource::<LoadQueue>()
+            .init_resource::<LodChangeRate>()
+            .add_event::<LodNodeAdded>()
+            .add_event::<LodNodeRemoved>()
+            .add_event::<LodNodeSplit>()
+            .add_event::<LodNodeMerged>()
+            .configure_sets(
+                (
+                    OctreeLodSet::Chunks,
+                    OctreeLodSet::Load.run_if(resource_exists::<ChunkGenerator>()),
+                    OctreeLodSet::Render,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                (
+                    update_octree::<T>,
+                    validate_balance::<T>
+                        .run_if(|settings: Res<OctreeSettings>| settings.validate_balance),
+                )
+                    .chain()
+                    .in_set(OctreeLodSet::UpdateTree)
+                    .in_schedule(CoreSchedule::FixedUpdate),
+            )
+            .add_system(update_lod_timestep.run_if(resource_changed::<OctreeSettings>()))
+            .add_system(measure_lod_change_rate)
+            .add_system(update_chunks::<T>.in_set(OctreeLodSet::Chunks))
+            .add_systems(
+                (
+                    invalidate_seams,
+                    update_load_focus,
+                    start_chunk_jobs::<T>,
+                    poll_chunk_jobs::<T>,
+                    retire_chunks::<T>,
+                )
+                    .chain()
+                    .in_set(OctreeLodSet::Load),
+            );
+    }
+}
+
+/// Draws the nodes of the [`Octree`] and the transition faces between levels with debug lines.
+pub struct OctreeLodDebugPlugin<T: NodePayload = ()> {
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T: NodePayload> Default for OctreeLodDebugPlugin<T> {
+    fn default() -> Self {
+        Self {
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<T: NodePayload> Plugin for OctreeLodDebugPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(DebugLinesPlugin::default()).add_system(
+            render::<T>
+                .in_set(OctreeLodSet::Render)
+                .run_if(|settings: Res<OctreeSettings>| {
+                    settings.draw_nodes || settings.draw_transitions
+                }),
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::{cmp::Ordering, collections::BinaryHeap, sync::Arc};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::{HashSet, Instant},
+};
+use futures_lite::future;
+use grid_tree::{Level, NodeKey};
+
+use crate::{
+    chunk::{ChunkKey, Chunks, RetiringChunk},
+    mesh::{surface_nets, ChunkMaterial, ChunkNeighborhood, RemeshChunk, SurfaceNetsBuffer},
+    octree::{find_leaf, NodePayload, Octree},
+    voxel::{ChunkGenerator, ChunkVoxels},
+};
+
+/// Where a chunk is in its loading process. Dropping the state cancels the running job, so
+/// despawning a chunk is enough to stop working on it.
+#[derive(Component, Default)]
+pub enum ChunkState {
+    /// Waiting for its voxels to be sampled.
+    #[default]
+    Queued,
+    Generating(Task<ChunkVoxels>),
+    Meshing(Task<SurfaceNetsBuffer>),
+    /// Has its voxels and, unless it has no surface or there is no [`ChunkMaterial`] to mesh it
+    /// with, its mesh.
+    Ready,
+}
+
+/// Caps the jobs started by [`start_chunk_jobs`] in a single frame, so that a burst of LOD
+/// changes is spread over several frames instead of stalling one.
+#[derive(Resource, Reflect, Clone, Debug)]
+#[reflect(Resource)]
+pub struct LoadBudget {
+    /// Maximum number of jobs started per frame.
+    pub jobs_per_frame: usize,
+    /// Time the main thread may spend starting jobs per frame, in milliseconds.
+    pub millis_per_frame: f32,
+    /// How much chunks behind the viewer are delayed: their distance is multiplied by up to
+    /// `1 + view_weight`. Zero orders chunks by distance only.
+    pub view_weight: f32,
+}
+
+impl Default for LoadBudget {
+    fn default() -> Self {
+        Self {
+            jobs_per_frame: 16,
+            millis_per_frame: 2.0,
+            view_weight: 1.0,
+        }
+    }
+}
+
+/// Where the chunks should be loaded first, filled by the app from its targets and camera.
+#[derive(Resource, Default)]
+pub struct LoadFocus {
+    /// Chunks are ordered by their distance to the nearest of these.
+    pub positions: Vec<Vec3>,
+    /// The position and forward direction of the viewer, if any.
+    pub view: Option<(Vec3, Vec3)>,
+}
+
+impl LoadFocus {
+    /// The order in which the chunk is loaded, lower first.
+    fn priority(&self, chunk: &ChunkKey, view_weight: f32) -> f32 {
+        let (min, max) = (chunk.min.as_vec3(), chunk.max.as_vec3());
+        let distance = self
+            .positions
+            .iter()
+            .map(|&position| position.distance(position.clamp(min, max)))
+            .min_by(f32::total_cmp)
+            .unwrap_or(0.0);
+
+        let Some((origin, forward)) = self.view else {
+            return distance;
+        };
+        let facing = forward.dot((((min + max) / 2.0) - origin).normalize_or_zero());
+        distance * (1.0 + view_weight * (1.0 - facing) / 2.0)
+    }
+}
+
+/// The chunks waiting for a job, either because they were just spawned or because their seams
+/// changed.
+#[derive(Resource, Default)]
+pub struct LoadQueue {
+    pending: HashSet<Entity>,
+}
+
+struct QueuedChunk {
+    priority: f32,
+    entity: Entity,
+}
+
+impl PartialEq for QueuedChunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedChunk {}
+
+impl PartialOrd for QueuedChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedChunk {
+    /// Reversed, so that [`BinaryHeap`] pops the lowest priority first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
+/// Starts sampling the voxels of the new chunks and meshing the chunks whose seams changed,
+/// nearest first, until the [`LoadBudget`] of the frame is spent. The others stay queued.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn start_chunk_jobs<T: NodePayload>(
+    mut commands: Commands,
+    tree: Res<Octree<T>>,
+    generator: Res<ChunkGenerator>,
+    material: Option<Res<ChunkMaterial>>,
+    budget: Res<LoadBudget>,
+    focus: Res<LoadFocus>,
+    mut queue: ResMut<LoadQueue>,
+    // Both queries access the load state.
+    mut queries: ParamSet<(
+        Query<Entity, Or<(Added<ChunkState>, Added<RemeshChunk>)>>,
+        Query<(
+            &ChunkKey,
+            &mut ChunkState,
+            Option<&ChunkVoxels>,
+            Option<&RemeshChunk>,
+        )>,
+    )>,
+) {
+    let start = Instant::now();
+    queue.pending.extend(&queries.p0());
+    let mut chunks = queries.p1();
+
+    // Despawned chunks are dropped from the queue here.
+    let mut jobs: BinaryHeap<_> = queue
+        .pending
+        .iter()
+        .filter_map(|&entity| {
+            let (chunk, ..) = chunks.get(entity).ok()?;
+            Some(QueuedChunk {
+                priority: focus.priority(chunk, budget.view_weight),
+                entity,
+            })
+        })
+        .collect();
+    queue.pending.clear();
+
+    let mut started = 0;
+    while let Some(QueuedChunk { entity, .. }) = jobs.pop() {
+        if started >= budget.jobs_per_frame
+            || start.elapsed().as_secs_f32() * 1000.0 >= budget.millis_per_frame
+        {
+            queue.pending.insert(entity);
+            queue.pending.extend(jobs.into_iter().map(|job| job.entity));
+            break;
+        }
+
+        let Ok((chunk, mut state, voxels, remesh)) = chunks.get_mut(entity) else {
+            continue;
+        };
+        if remesh.is_some() {
+            commands.entity(entity).remove::<RemeshChunk>();
+        }
+
+        match (&*state, voxels) {
+            (ChunkState::Queued, _) => {
+                let generator = generator.clone();
+                let key = chunk.key();
+                *state = ChunkState::Generating(
+                    AsyncComputeTaskPool::get()
+                        .spawn(async move { ChunkVoxels::sample(generator.0.as_ref(), key) }),
+                );
+                started += 1;
+            }
+            // Chunks that are still generating will be meshed with the leaves of the time their
+            // voxels are ready.
+            (ChunkState::Meshing(_) | ChunkState::Ready, Some(voxels))
+                if remesh.is_some() && material.is_some() && !voxels.is_uniform() =>
+            {
+                *state = ChunkState::Meshing(spawn_meshing(
+                    chunk.key(),
+                    voxels.clone(),
+                    tree.leaves_snapshot(),
+                    tree.root_level(),
+                    generator.clone(),
+                ));
+                started += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Moves the chunks whose job finished to their next state. Chunks are only meshed when the app
+/// has mesh assets and a [`ChunkMaterial`], so that they can be generated without a renderer.
+pub fn poll_chunk_jobs<T: NodePayload>(
+    mut commands: Commands,
+    mut meshes: Option<ResMut<Assets<Mesh>>>,
+    material: Option<Res<ChunkMaterial>>,
+    tree: Res<Octree<T>>,
+    generator: Res<ChunkGenerator>,
+    chunk_entities: Res<Chunks>,
+    mut chunks: Query<(Entity, &ChunkKey, &mut ChunkState)>,
+) {
+    let meshing = meshes.is_some() && material.is_some();
+
+    for (entity, chunk, mut state) in &mut chunks {
+        // A job can finish in the frame its chunk is despawned to cancel it.
+        if !chunk_entities.contains(chunk.key(), entity) {
+            continue;
+        }
+
+        match &mut *state {
+            ChunkState::Generating(task) => {
+                let Some(voxels) = future::block_on(future::poll_once(task)) else {
+                    continue;
+                };
+
+                *state = if voxels.is_uniform() || !meshing {
+                    ChunkState::Ready
+                } else {
+                    ChunkState::Meshing(spawn_meshing(
+                        chunk.key(),
+                        voxels.clone(),
+                        tree.leaves_snapshot(),
+                        tree.root_level(),
+                        generator.clone(),
+                    ))
+                };
+                commands.entity(entity).insert(voxels);
+            }
+            ChunkState::Meshing(task) => {
+                let Some(buffer) = future::block_on(future::poll_once(task)) else {
+                    continue;
+                };
+
+                let mut entity_commands = commands.entity(entity);
+                match (&mut meshes, &material) {
+                    (Some(meshes), Some(material)) if !buffer.indices.is_empty() => {
+                        entity_commands
+                            .insert((meshes.add(buffer.into_mesh()), material.0.clone()));
+                    }
+                    _ => {
+                        entity_commands.remove::<Handle<Mesh>>();
+                    }
+                }
+                *state = ChunkState::Ready;
+            }
+            ChunkState::Queued | ChunkState::Ready => {}
+        }
+    }
+}
+
+/// Meshes a chunk off the main thread, against the leaves of the tree at the time of the call.
+fn spawn_meshing(
+    key: NodeKey<IVec3>,
+    voxels: ChunkVoxels,
+    leaves: Arc<HashSet<NodeKey<IVec3>>>,
+    root_level: Level,
+    generator: ChunkGenerator,
+) -> Task<SurfaceNetsBuffer> {
+    AsyncComputeTaskPool::get().spawn(async move {
+        let leaf_at = |position| find_leaf(&leaves, root_level, position);
+        let neighborhood = ChunkNeighborhood {
+            key,
+            leaf_at: &leaf_at,
+            generator: generator.0.as_ref(),
+        };
+        surface_nets(&voxels, &neighborhood)
+    })
+}
+
+/// Despawns the retiring chunks once every leaf covering their region is ready, and hides the
+/// ready chunks that still overlap a retiring one so the two meshes aren't drawn on top of each
+/// other. Until then, the retiring chunk fills the hole left by the ones that are loading.
+pub fn retire_chunks<T: NodePayload>(
+    mut commands: Commands,
+    tree: Res<Octree<T>>,
+    mut chunks: ResMut<Chunks>,
+    states: Query<&ChunkState>,
+    mut visibilities: Query<(&ChunkKey, &mut Visibility), Without<RetiringChunk>>,
+) {
+    let leaves = &chunks.leaves;
+    let is_ready = |key: NodeKey<IVec3>| {
+        leaves
+            .get(&key)
+            .is_some_and(|&entity| matches!(states.get(entity), Ok(ChunkState::Ready)))
+    };
+
+    let loaded: Vec<_> = chunks
+        .retiring
+        .keys()
+        .copied()
+        .filter(|&key| tree.leaves_overlapping(key).into_iter().all(&is_ready))
+        .collect();
+    for key in loaded {
+        if let Some(entity) = chunks.retiring.remove(&key) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+
+    for (chunk, mut visibility) in &mut visibilities {
+        let hidden = chunks
+            .retiring
+            .keys()
+            .any(|&retiring| overlaps(retiring, chunk.key()));
+        let new_visibility = if hidden {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        if *visibility != new_visibility {
+            *visibility = new_visibility;
+        }
+    }
+}
+
+/// Whether one of the nodes contains the other.
+fn overlaps(a: NodeKey<IVec3>, b: NodeKey<IVec3>) -> bool {
+    let (coarse, fine) = if a.level >= b.level { (a, b) } else { (b, a) };
+    fine.coordinates >> (coarse.level - fine.level) as i32 == coarse.coordinates
+}
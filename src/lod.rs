@@ -0,0 +1,351 @@
+use std::time::Duration;
+
+use bevy::{prelude::*, utils::HashMap};
+use grid_tree::Level;
+
+use crate::{
+    balance::{find_balance_violations, Adjacency},
+    load::LoadFocus,
+    octree::{LodEventWriters, LodNodeMerged, LodNodeSplit, NodePayload, Octree},
+    refine::{refine, LodWorker, RefineSettings},
+    subdivision::{check_lod_range, LodObserver, LodRangeError, LodStrategy},
+};
+
+/// Past this height, the shifts done by [`CanSubdivide::can_subdivide`](crate::subdivision::CanSubdivide::can_subdivide) overflow
+/// `i32`. [`octree_i64`](crate::octree_i64) does the same math for deeper trees.
+pub const MAX_OCTREE_HEIGHT: Level = 20;
+
+/// Settings of the octree, editable at runtime from the inspector.
+#[derive(Resource, Reflect, Clone, Debug)]
+#[reflect(Resource)]
+pub struct OctreeSettings {
+    /// Number of levels of the tree, from 1 to [`MAX_OCTREE_HEIGHT`]. Changing it rebuilds the tree
+    /// from scratch.
+    pub height: Level,
+    /// Detail used by the targets that don't have their own [`LodSettings`].
+    pub detail: i32,
+    /// How much further, in half node widths, an observer must be from a subdivided node before it
+    /// is merged back. Keeps nodes from flickering when an observer stays near a boundary.
+    pub merge_margin: i32,
+    /// When set, the tree is re-evaluated once an observer moved this far since the last
+    /// evaluation, rather than whenever it enters another level 0 node.
+    pub movement_threshold: Option<f32>,
+    /// Seconds between two updates of the tree, which runs on a fixed timestep.
+    pub lod_timestep: f32,
+    /// Refine a copy of the tree on another thread and make the changes it found to the tree once
+    /// done, rather than refining the tree within the frame.
+    pub background_update: bool,
+    /// When set, leaves are refined until none of them is more than one level finer than one of
+    /// its neighbors.
+    pub balance: Option<Adjacency>,
+    /// Log the pairs of neighboring leaves that are more than one level apart.
+    pub validate_balance: bool,
+    /// Draw the bounds of every node on top of the chunk meshes.
+    pub draw_nodes: bool,
+    /// Draw the faces where a leaf touches a coarser one, across which chunk meshes are stitched.
+    pub draw_transitions: bool,
+    pub root_color: Color,
+    pub node_color: Color,
+    pub leaf_color: Color,
+    pub transition_color: Color,
+}
+
+impl Default for OctreeSettings {
+    fn default() -> Self {
+        Self {
+            height: 10,
+            detail: 1,
+            merge_margin: 1,
+            movement_threshold: None,
+            lod_timestep: 1.0 / 30.0,
+            background_update: true,
+            balance: None,
+            validate_balance: false,
+            draw_nodes: false,
+            draw_transitions: false,
+            root_color: Color::RED,
+            node_color: Color::WHITE,
+            leaf_color: Color::GREEN,
+            transition_color: Color::FUCHSIA,
+        }
+    }
+}
+
+impl OctreeSettings {
+    /// The height, clamped to the supported ones so that a tree can be built anyway. The tree isn't
+    /// refined while [`OctreeSettings::check_lod_range`] fails.
+    pub fn height(&self) -> Level {
+        self.height.clamp(1, MAX_OCTREE_HEIGHT)
+    }
+
+    /// Checks the height, and that the tree can be refined with [`OctreeSettings::detail`].
+    pub fn check_lod_range(&self) -> Result<(), LodRangeError> {
+        self.check_detail(self.detail)
+    }
+
+    /// Checks the height, and that the tree can be refined with `detail`, merge margin included.
+    pub fn check_detail(&self, detail: i32) -> Result<(), LodRangeError> {
+        check_lod_range(self.height, detail)?;
+        check_lod_range(self.height, detail.saturating_add(self.merge_margin.max(0)))
+    }
+
+    pub fn root_length(&self) -> i32 {
+        1 << (self.height() - 1)
+    }
+
+    pub fn default_lod_settings(&self) -> LodSettings {
+        LodSettings {
+            detail: self.detail,
+            min_level: 0,
+        }
+    }
+}
+
+/// How often nodes are split and merged, averaged over the last second. Shown in the inspector to
+/// tune [`OctreeSettings::merge_margin`].
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct LodChangeRate {
+    pub splits_per_second: f32,
+    pub merges_per_second: f32,
+}
+
+/// An observer the tree is refined around.
+#[derive(Component, Reflect)]
+pub struct Target;
+
+/// How much detail a [`Target`] asks for. Targets without this component follow
+/// [`OctreeSettings::detail`].
+#[derive(Component, Reflect, Clone, Copy, Debug)]
+#[reflect(Component)]
+pub struct LodSettings {
+    /// See [`LodObserver::detail`].
+    pub detail: i32,
+    /// The finest level this target subdivides the tree to.
+    pub min_level: Level,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            detail: OctreeSettings::default().detail,
+            min_level: 0,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_octree<T: NodePayload>(
+    mut tree: ResMut<Octree<T>>,
+    settings: Res<OctreeSettings>,
+    strategy: Res<LodStrategy>,
+    target_query: Query<(Entity, &GlobalTransform, Option<&LodSettings>), With<Target>>,
+    camera_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &Camera,
+            &Projection,
+            Option<&LodSettings>,
+        ),
+        With<Camera3d>,
+    >,
+    mut worker: ResMut<LodWorker>,
+    mut evaluated: Local<HashMap<Entity, LodObserver>>,
+    mut dirty: Local<bool>,
+    mut events: LodEventWriters,
+) {
+    // Settings changed while a job runs are picked up by the next one.
+    *dirty |= settings.is_changed() || strategy.is_changed();
+    if strategy.is_changed() {
+        // The running job would bring back the refinement of the old strategy.
+        worker.cancel();
+        tree.forget_refinement();
+    }
+
+    if settings.background_update {
+        if let Some(changes) = worker.poll(&mut tree) {
+            events.send(changes);
+        }
+        if worker.is_busy() {
+            return;
+        }
+    } else {
+        // Otherwise the job would be polled once the background updates are back on, and would
+        // make its changes to a tree the synchronous updates changed since.
+        worker.cancel();
+    }
+
+    let observer = |transform: &GlobalTransform, lod_settings: Option<&LodSettings>| {
+        let lod_settings = lod_settings
+            .copied()
+            .unwrap_or_else(|| settings.default_lod_settings());
+        LodObserver {
+            position: transform.translation(),
+            detail: lod_settings.detail,
+            min_level: lod_settings.min_level,
+            projection_scale: None,
+        }
+    };
+
+    let mut observers: HashMap<_, _> = target_query
+        .iter()
+        .map(|(entity, target_pos, lod_settings)| (entity, observer(target_pos, lod_settings)))
+        .collect();
+
+    if strategy.uses_cameras() {
+        observers.extend(
+            camera_query
+                .iter()
+                .filter(|(_, _, camera, ..)| camera.is_active)
+                .filter_map(|(entity, transform, camera, projection, lod_settings)| {
+                    let Projection::Perspective(perspective) = projection else {
+                        return None;
+                    };
+                    let viewport_size = camera.physical_viewport_size()?;
+
+                    let observer = LodObserver {
+                        projection_scale: Some(
+                            viewport_size.y as f32 / (2.0 * (perspective.fov / 2.0).tan()),
+                        ),
+                        ..observer(transform, lod_settings)
+                    };
+                    Some((entity, observer))
+                }),
+        );
+    }
+
+    // Nothing to do until an observer comes or goes, or one of them moved far enough to change
+    // the layout.
+    let unchanged = observers.len() == evaluated.len()
+        && observers.iter().all(|(entity, observer)| {
+            evaluated
+                .get(entity)
+                .is_some_and(|last| !observer_moved(last, observer, settings.movement_threshold))
+        });
+    if unchanged && !*dirty {
+        return;
+    }
+    *dirty = false;
+    *evaluated = observers;
+    let observers: Vec<_> = evaluated.values().copied().collect();
+
+    // Logged once per evaluation, until the settings or the observers change again.
+    if let Err(error) = settings.check_lod_range().and_then(|()| {
+        observers
+            .iter()
+            .try_for_each(|observer| settings.check_detail(observer.detail))
+    }) {
+        error!("Not refining the octree: {error}");
+        return;
+    }
+
+    let refine_settings = RefineSettings {
+        height: settings.height(),
+        merge_margin: settings.merge_margin,
+        balance: settings.balance,
+    };
+    if settings.background_update {
+        worker.start(&tree, observers, strategy.0.clone(), refine_settings);
+        return;
+    }
+
+    worker.mark_stale();
+    events.send(refine(
+        &mut tree,
+        &observers,
+        strategy.0.as_ref(),
+        refine_settings,
+    ));
+}
+
+/// Loads the chunks around the observers of the tree first, which are the targets plus the
+/// cameras when the [`LodStrategy`] uses them, and the ones in front of the camera before the ones
+/// behind it.
+pub fn update_load_focus(
+    mut focus: ResMut<LoadFocus>,
+    strategy: Res<LodStrategy>,
+    target_query: Query<&GlobalTransform, With<Target>>,
+    camera_query: Query<(&GlobalTransform, &Camera, &Projection), With<Camera3d>>,
+) {
+    focus.positions.clear();
+    focus
+        .positions
+        .extend(target_query.iter().map(|transform| transform.translation()));
+
+    // The same cameras as the ones `update_octree` refines the tree around.
+    if strategy.uses_cameras() {
+        focus.positions.extend(
+            camera_query
+                .iter()
+                .filter(|(_, camera, projection)| {
+                    camera.is_active
+                        && matches!(projection, Projection::Perspective(_))
+                        && camera.physical_viewport_size().is_some()
+                })
+                .map(|(transform, ..)| transform.translation()),
+        );
+    }
+
+    focus.view = camera_query
+        .iter()
+        .find(|(_, camera, _)| camera.is_active)
+        .map(|(transform, ..)| (transform.translation(), transform.forward()));
+}
+
+/// Whether the tree must be re-evaluated for `observer`, which was at `last` the last time it was.
+/// By default that is when it enters another level 0 node, or after it moved `movement_threshold`
+/// if set.
+fn observer_moved(
+    last: &LodObserver,
+    observer: &LodObserver,
+    movement_threshold: Option<f32>,
+) -> bool {
+    if last.detail != observer.detail
+        || last.min_level != observer.min_level
+        || last.projection_scale != observer.projection_scale
+    {
+        return true;
+    }
+
+    match movement_threshold {
+        Some(threshold) => last.position.distance(observer.position) >= threshold,
+        None => last.key() != observer.key(),
+    }
+}
+
+pub fn validate_balance<T: NodePayload>(tree: Res<Octree<T>>, settings: Res<OctreeSettings>) {
+    let violations = find_balance_violations(&tree, settings.balance.unwrap_or_default());
+    if let Some(first) = violations.first() {
+        warn!(
+            "{} leaves are more than one level finer than a neighbor, e.g. {:?} next to {:?}",
+            violations.len(),
+            first.leaf,
+            first.coarse_neighbor
+        );
+    }
+}
+
+pub fn measure_lod_change_rate(
+    time: Res<Time>,
+    mut rate: ResMut<LodChangeRate>,
+    mut split_events: EventReader<LodNodeSplit>,
+    mut merged_events: EventReader<LodNodeMerged>,
+    mut window: Local<(f32, usize, usize)>,
+) {
+    let (elapsed, splits, merges) = &mut *window;
+    *elapsed += time.delta_seconds();
+    *splits += split_events.iter().count();
+    *merges += merged_events.iter().count();
+
+    if *elapsed >= 1.0 {
+        rate.splits_per_second = *splits as f32 / *elapsed;
+        rate.merges_per_second = *merges as f32 / *elapsed;
+        *window = default();
+    }
+}
+
+pub fn update_lod_timestep(settings: Res<OctreeSettings>, mut fixed_time: ResMut<FixedTime>) {
+    // A zero period would keep the fixed update looping forever.
+    fixed_time.period = Duration::from_secs_f32(settings.lod_timestep.max(0.001));
+}
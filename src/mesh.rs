@@ -0,0 +1,337 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    utils::HashMap,
+};
+use grid_tree::NodeKey;
+
+use crate::{
+    chunk::{ChunkKey, Chunks},
+    octree::{LodNodeAdded, LodNodeMerged, LodNodeRemoved, LodNodeSplit},
+    voxel::{chunk_step, linearize, sample_position, ChunkVoxels, DensityGenerator, CHUNK_CELLS},
+};
+
+/// The material shared by all chunk meshes.
+#[derive(Resource, Deref)]
+pub struct ChunkMaterial(pub Handle<StandardMaterial>);
+
+/// Put on the chunks whose mesh must be rebuilt because the leaves around them changed.
+#[derive(Component)]
+pub struct RemeshChunk;
+
+/// The output of [`surface_nets`], in the chunk's local space.
+#[derive(Default)]
+pub struct SurfaceNetsBuffer {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl SurfaceNetsBuffer {
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+
+    fn push_vertex(&mut self, position: Vec3, normal: Vec3) -> u32 {
+        let index = self.positions.len() as u32;
+        self.positions.push(position.to_array());
+        self.normals.push(normal.to_array());
+        index
+    }
+
+    /// Pushes the triangle unless it is degenerate, which happens when a quad reaches into a
+    /// coarser chunk and several of its corners land in the same coarse cell.
+    fn push_triangle(&mut self, a: u32, b: u32, c: u32) {
+        if a != b && b != c && c != a {
+            self.indices.extend_from_slice(&[a, b, c]);
+        }
+    }
+}
+
+/// What the mesher needs to know about the surroundings of the chunk being meshed.
+pub struct ChunkNeighborhood<'a> {
+    pub key: NodeKey<IVec3>,
+    /// The leaf containing a world position.
+    pub leaf_at: &'a dyn Fn(Vec3) -> Option<NodeKey<IVec3>>,
+    /// Used to compute the vertices of the neighboring chunks' cells.
+    pub generator: &'a dyn DensityGenerator,
+}
+
+/// Offsets of the 8 corners of a cell, the bits of the index being the x, y and z offsets.
+const CORNERS: [UVec3; 8] = [
+    UVec3::new(0, 0, 0),
+    UVec3::new(1, 0, 0),
+    UVec3::new(0, 1, 0),
+    UVec3::new(1, 1, 0),
+    UVec3::new(0, 0, 1),
+    UVec3::new(1, 0, 1),
+    UVec3::new(0, 1, 1),
+    UVec3::new(1, 1, 1),
+];
+
+/// Pairs of corners linked by the 12 edges of a cell.
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// The axes of the sample edges, each followed by the two other axes in cyclic order.
+const AXES: [(UVec3, UVec3, UVec3); 3] = [
+    (UVec3::X, UVec3::Y, UVec3::Z),
+    (UVec3::Y, UVec3::Z, UVec3::X),
+    (UVec3::Z, UVec3::X, UVec3::Y),
+];
+
+/// Where the vertex of a cell around a sample edge comes from.
+#[derive(Clone, Copy)]
+enum CellSource {
+    /// One of the chunk's own cells.
+    Own,
+    /// A cell of another leaf, which may be coarser than the chunk.
+    Leaf(NodeKey<IVec3>),
+    /// Outside of the tree, the chunk's grid is extended there.
+    Outside,
+}
+
+/// Naive surface nets: one vertex per cell crossed by the surface, at the average of the points
+/// where the surface crosses the cell's edges, and one quad per sample edge crossed by the surface.
+///
+/// Seams between chunks are generated by the finer side: a quad is emitted by the finest chunk
+/// touching its four cells, the cells that belong to coarser chunks using the vertex of the coarse
+/// cell containing them. Since the generator is deterministic, those are the very vertices the
+/// coarse chunk computes for itself, and the mesh stays watertight across level changes. A coarse
+/// cell can miss a crossing seen at the chunk's resolution, in which case the quad uses the
+/// crossing clamped into the coarse cell, so that the seam is still closed.
+pub fn surface_nets(voxels: &ChunkVoxels, neighborhood: &ChunkNeighborhood) -> SurfaceNetsBuffer {
+    let key = neighborhood.key;
+    let chunk_min = sample_position(key, IVec3::ONE);
+
+    let mut buffer = SurfaceNetsBuffer::default();
+    let mut own_vertices = vec![u32::MAX; voxels.samples.len()];
+    let mut other_vertices = HashMap::<(NodeKey<IVec3>, IVec3), u32>::default();
+
+    for z in 1..=CHUNK_CELLS {
+        for y in 1..=CHUNK_CELLS {
+            for x in 1..=CHUNK_CELLS {
+                let cell = UVec3::new(x, y, z);
+                let densities = CORNERS.map(|corner| voxels.get(cell + corner));
+                if let Some((position, normal)) = cell_vertex(densities) {
+                    let position = (cell.as_vec3() + position - 1.0) * voxels.step;
+                    own_vertices[linearize(cell)] = buffer.push_vertex(position, normal);
+                }
+            }
+        }
+    }
+
+    let cell_source = |cell: UVec3| {
+        if cell.cmpge(UVec3::ONE).all() && cell.cmple(UVec3::splat(CHUNK_CELLS)).all() {
+            return CellSource::Own;
+        }
+
+        let center = chunk_min + (cell.as_vec3() - 0.5) * voxels.step;
+        match (neighborhood.leaf_at)(center) {
+            Some(leaf) => CellSource::Leaf(leaf),
+            None => CellSource::Outside,
+        }
+    };
+
+    // The edges are the ones of the chunk's closed bounds, neighbors sharing some of them.
+    for z in 1..=CHUNK_CELLS + 1 {
+        for y in 1..=CHUNK_CELLS + 1 {
+            for x in 1..=CHUNK_CELLS + 1 {
+                let sample = UVec3::new(x, y, z);
+                let density = voxels.get(sample);
+                let inside = density < 0.0;
+
+                for (a, b, c) in AXES {
+                    if sample.dot(a) > CHUNK_CELLS {
+                        continue;
+                    }
+                    let next_density = voxels.get(sample + a);
+                    if inside == (next_density < 0.0) {
+                        continue;
+                    }
+
+                    let cells = [sample, sample - b, sample - b - c, sample - c];
+                    let sources = cells.map(&cell_source);
+                    let levels = sources.map(|source| match source {
+                        CellSource::Own => Some(key.level),
+                        CellSource::Leaf(leaf) => Some(leaf.level),
+                        CellSource::Outside => None,
+                    });
+
+                    // Finer chunks emit this part of the surface themselves, and chunks of the
+                    // same level agree on a single owner.
+                    if levels.iter().flatten().any(|&level| level < key.level) {
+                        continue;
+                    }
+                    let owner = levels.iter().position(|&level| level == Some(key.level));
+                    if !matches!(owner.map(|i| sources[i]), Some(CellSource::Own)) {
+                        continue;
+                    }
+
+                    // Where the surface crosses the edge, in the chunk's space, facing outside.
+                    let t = density / (density - next_density);
+                    let crossing = (sample.as_vec3() - 1.0 + a.as_vec3() * t) * voxels.step;
+                    let crossing_normal = if inside { a.as_vec3() } else { -a.as_vec3() };
+
+                    let mut quad = [0; 4];
+                    for (i, (cell, source)) in cells.into_iter().zip(sources).enumerate() {
+                        let center = chunk_min + (cell.as_vec3() - 0.5) * voxels.step;
+                        let leaf = match source {
+                            CellSource::Own => {
+                                quad[i] = own_vertices[linearize(cell)];
+                                continue;
+                            }
+                            CellSource::Leaf(leaf) => leaf,
+                            CellSource::Outside => key,
+                        };
+                        quad[i] = other_vertex(
+                            &mut buffer,
+                            &mut other_vertices,
+                            neighborhood,
+                            leaf,
+                            center,
+                            (crossing, crossing_normal),
+                        );
+                    }
+
+                    // The quad is counter-clockwise when seen from +a, which is where the surface
+                    // faces when the solid is on the side of `sample`.
+                    let [v0, v1, v2, v3] = quad;
+                    if inside {
+                        buffer.push_triangle(v0, v1, v2);
+                        buffer.push_triangle(v0, v2, v3);
+                    } else {
+                        buffer.push_triangle(v0, v3, v2);
+                        buffer.push_triangle(v0, v2, v1);
+                    }
+                }
+            }
+        }
+    }
+
+    buffer
+}
+
+/// The vertex of the cell of `leaf` containing `point`, computed from the generator and added to
+/// the buffer the first time it is needed. If the surface doesn't cross that cell, which happens
+/// when it is coarser than the chunk, `fallback` is clamped into the cell instead.
+fn other_vertex(
+    buffer: &mut SurfaceNetsBuffer,
+    cache: &mut HashMap<(NodeKey<IVec3>, IVec3), u32>,
+    neighborhood: &ChunkNeighborhood,
+    leaf: NodeKey<IVec3>,
+    point: Vec3,
+    (fallback, fallback_normal): (Vec3, Vec3),
+) -> u32 {
+    let step = chunk_step(leaf.level);
+    let leaf_min = sample_position(leaf, IVec3::ONE);
+    let cell = ((point - leaf_min) / step).floor().as_ivec3() + IVec3::ONE;
+
+    *cache.entry((leaf, cell)).or_insert_with(|| {
+        let densities = CORNERS.map(|corner| {
+            let sample = sample_position(leaf, cell + corner.as_ivec3());
+            neighborhood.generator.density(sample)
+        });
+
+        // Same computation as the leaf does in its own space, then moved to the chunk's space.
+        let chunk_min = sample_position(neighborhood.key, IVec3::ONE);
+        let cell_min = leaf_min - chunk_min + (cell - IVec3::ONE).as_vec3() * step;
+        match cell_vertex(densities) {
+            Some((position, normal)) => buffer.push_vertex(cell_min + position * step, normal),
+            None => buffer.push_vertex(fallback.clamp(cell_min, cell_min + step), fallback_normal),
+        }
+    })
+}
+
+/// The vertex of a cell from the densities at its corners, in cell units from its minimum corner,
+/// along with its normal. `None` if the surface doesn't cross the cell.
+fn cell_vertex(densities: [f32; 8]) -> Option<(Vec3, Vec3)> {
+    if densities.iter().all(|&d| d < 0.0) || densities.iter().all(|&d| d >= 0.0) {
+        return None;
+    }
+
+    let mut sum = Vec3::ZERO;
+    let mut crossings = 0;
+    for (a, b) in EDGES {
+        let (d_a, d_b) = (densities[a], densities[b]);
+        if (d_a < 0.0) != (d_b < 0.0) {
+            let t = d_a / (d_a - d_b);
+            sum += CORNERS[a].as_vec3().lerp(CORNERS[b].as_vec3(), t);
+            crossings += 1;
+        }
+    }
+
+    // Finite differences between the faces of the cell.
+    let gradient = Vec3::new(
+        densities[1] + densities[3] + densities[5] + densities[7]
+            - densities[0]
+            - densities[2]
+            - densities[4]
+            - densities[6],
+        densities[2] + densities[3] + densities[6] + densities[7]
+            - densities[0]
+            - densities[1]
+            - densities[4]
+            - densities[5],
+        densities[4] + densities[5] + densities[6] + densities[7]
+            - densities[0]
+            - densities[1]
+            - densities[2]
+            - densities[3],
+    );
+
+    Some((sum / crossings as f32, gradient.normalize_or_zero()))
+}
+
+/// Flags the chunks touching leaves that appeared or disappeared, since their seams depend on the
+/// level of their neighbors. Only the chunks of current leaves are flagged: the others may have
+/// been despawned by [`update_chunks`](crate::chunk::update_chunks) earlier in the frame.
+pub fn invalidate_seams(
+    mut commands: Commands,
+    chunk_entities: Res<Chunks>,
+    mut added_events: EventReader<LodNodeAdded>,
+    mut removed_events: EventReader<LodNodeRemoved>,
+    mut split_events: EventReader<LodNodeSplit>,
+    mut merged_events: EventReader<LodNodeMerged>,
+    chunks: Query<(Entity, &ChunkKey), With<ChunkVoxels>>,
+) {
+    let changed: Vec<_> = added_events
+        .iter()
+        .map(|event| event.0)
+        .chain(removed_events.iter().map(|event| event.0))
+        .chain(split_events.iter().map(|event| event.0))
+        .chain(merged_events.iter().map(|event| event.0))
+        .collect();
+    if changed.is_empty() {
+        return;
+    }
+
+    for (entity, chunk) in &chunks {
+        if chunk_entities.leaves.get(&chunk.key()) != Some(&entity) {
+            continue;
+        }
+
+        let touches = changed
+            .iter()
+            .any(|node| chunk.min.cmple(node.max).all() && node.min.cmple(chunk.max).all());
+        if touches {
+            commands.entity(entity).insert(RemeshChunk);
+        }
+    }
+}
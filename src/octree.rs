@@ -0,0 +1,457 @@
+use std::{ops::Deref, sync::Arc};
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashSet};
+use grid_tree::{
+    BranchShape, ChildRelation, Level, NodeEntry, NodeKey, NodePtr, OctreeI32, OctreeShapeI32,
+    VisitCommand,
+};
+
+use crate::refine::Refinement;
+
+/// Data stored in each node of an [`Octree`], created and dropped along with the node.
+pub trait NodePayload: Sized + Send + Sync + 'static {
+    /// Builds the payload of a node that was just inserted in the tree.
+    fn create(key: NodeKey<IVec3>) -> Self;
+
+    /// Called with the payload of a node that was just removed from the tree.
+    fn remove(self, _key: NodeKey<IVec3>) {}
+}
+
+impl NodePayload for () {
+    fn create(_key: NodeKey<IVec3>) -> Self {}
+}
+
+/// The LOD octree, holding a `T` in each node.
+#[derive(Resource)]
+pub struct Octree<T: NodePayload = ()> {
+    tree: OctreeI32<T>,
+    /// Keys of the nodes that currently have no children, shared with the snapshots taken by
+    /// [`Octree::leaves_snapshot`].
+    leaves: Arc<HashSet<NodeKey<IVec3>>>,
+    /// What the tree was last refined for, if its nodes still match it.
+    pub(crate) refinement: Option<Refinement>,
+}
+
+impl<T: NodePayload> Octree<T> {
+    pub fn new(height: Level) -> Self {
+        Self {
+            tree: OctreeI32::new(height),
+            leaves: Arc::default(),
+            refinement: None,
+        }
+    }
+
+    /// Makes the next refinement test every node again, for the changes it can't see by itself,
+    /// like another subdivision strategy.
+    pub fn forget_refinement(&mut self) {
+        self.refinement = None;
+    }
+
+    /// Whether `key` is in the tree and has children.
+    pub fn is_split(&self, key: NodeKey<IVec3>) -> bool {
+        !self.is_leaf(key) && self.tree.find_node(key).is_some()
+    }
+
+    /// Whether `key` is in the tree and has no children.
+    pub fn is_leaf(&self, key: NodeKey<IVec3>) -> bool {
+        self.leaves.contains(&key)
+    }
+
+    pub fn leaves(&self) -> impl Iterator<Item = NodeKey<IVec3>> + '_ {
+        self.leaves.iter().copied()
+    }
+
+    /// Keys of the nodes that have children.
+    pub fn split_nodes(&self) -> HashSet<NodeKey<IVec3>> {
+        let mut split_nodes = HashSet::default();
+        for (root_key, root_node) in self.tree.iter_roots() {
+            let root_ptr = NodePtr::new(root_key.level, root_node.self_ptr);
+            self.tree.visit_tree_depth_first(
+                root_ptr,
+                root_key.coordinates,
+                0,
+                |ptr, coordinates| {
+                    let key = NodeKey::new(ptr.level(), coordinates);
+                    if self.leaves.contains(&key) {
+                        VisitCommand::SkipDescendants
+                    } else {
+                        split_nodes.insert(key);
+                        VisitCommand::Continue
+                    }
+                },
+            );
+        }
+        split_nodes
+    }
+
+    /// The current leaves, for use off the main thread. The next update clones them only if the
+    /// snapshot is still alive by then.
+    pub fn leaves_snapshot(&self) -> Arc<HashSet<NodeKey<IVec3>>> {
+        self.leaves.clone()
+    }
+
+    /// The leaves covering the same region as `key`: the leaf containing it, or the leaves below
+    /// it. Empty if the region isn't part of the tree.
+    pub fn leaves_overlapping(&self, key: NodeKey<IVec3>) -> Vec<NodeKey<IVec3>> {
+        let node = LodNode::new(key);
+        match self.leaf_at((node.min + node.max).as_vec3() / 2.0) {
+            Some(leaf) if leaf.level >= key.level => vec![leaf],
+            Some(_) => {
+                let mut leaves = Vec::new();
+                if let Some(ptr) = self.tree.find_node(key) {
+                    self.tree.visit_tree_depth_first(
+                        ptr,
+                        key.coordinates,
+                        0,
+                        |ptr, coordinates| {
+                            let descendant = NodeKey::new(ptr.level(), coordinates);
+                            if self.leaves.contains(&descendant) {
+                                leaves.push(descendant);
+                            }
+                            VisitCommand::Continue
+                        },
+                    );
+                }
+                leaves
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// The leaf containing `position`, if any.
+    pub fn leaf_at(&self, position: Vec3) -> Option<NodeKey<IVec3>> {
+        find_leaf(&self.leaves, self.tree.root_level(), position)
+    }
+
+    pub fn payload(&self, key: NodeKey<IVec3>) -> Option<&T> {
+        let ptr = self.tree.find_node(key)?;
+        self.tree.get_value(ptr)
+    }
+
+    pub fn payload_mut(&mut self, key: NodeKey<IVec3>) -> Option<&mut T> {
+        let ptr = self.tree.find_node(key)?;
+        self.tree.get_value_mut(ptr)
+    }
+
+    /// Brings the trees rooted at `root_keys` in line with `can_subdivide`: leaves that now pass the
+    /// test are split and nodes that no longer pass it have their subtree merged back into them.
+    /// Nodes of the regions where the result didn't change are left untouched, and the roots that
+    /// aren't part of `root_keys` anymore are removed with all their descendants.
+    ///
+    /// `can_subdivide` is also told whether the node currently has children, so that it can use a
+    /// looser test to keep a node split than to split it.
+    ///
+    /// Existing nodes for which `may_change` is false are kept as they are, along with their whole
+    /// subtree, without being tested. It must be true for the nodes whose result may differ from
+    /// the last update and for their ancestors, so that the cost of an update scales with the
+    /// regions that changed rather than with the size of the tree.
+    pub fn update(
+        &mut self,
+        root_keys: &HashSet<NodeKey<IVec3>>,
+        mut can_subdivide: impl FnMut(NodeKey<IVec3>, bool) -> bool,
+        mut may_change: impl FnMut(NodeKey<IVec3>) -> bool,
+    ) -> LodChanges {
+        let mut changes = LodChanges::default();
+
+        let stale_roots: Vec<_> = self
+            .tree
+            .iter_roots()
+            .map(|(root_key, _)| *root_key)
+            .filter(|root_key| !root_keys.contains(root_key))
+            .collect();
+        for root_key in stale_roots {
+            let relation = ChildRelation {
+                child: root_key,
+                parent: None,
+            };
+            self.remove_tree(&relation, &mut changes.removed);
+        }
+
+        for &root_key in root_keys {
+            self.fill_tree(root_key, &mut can_subdivide, &mut may_change, &mut changes);
+        }
+
+        for &key in &changes.merged {
+            self.remove_descendants(key, &mut changes.removed);
+            Arc::make_mut(&mut self.leaves).insert(key);
+        }
+
+        changes
+    }
+
+    /// Makes the changes an [`Octree::update`] made to another tree that had the same nodes as this
+    /// one, creating and removing the payloads of the nodes they add and remove. Only these nodes
+    /// and the ancestors of the added ones are visited.
+    pub fn apply(&mut self, changes: &LodChanges) {
+        let root_level = self.tree.root_level();
+        let mut removed = Vec::new();
+        for &key in &changes.removed {
+            // The other nodes are removed along with their root or the node that was merged.
+            if key.level == root_level && self.tree.find_node(key).is_some() {
+                let relation = ChildRelation {
+                    child: key,
+                    parent: None,
+                };
+                self.remove_tree(&relation, &mut removed);
+            }
+        }
+        for &key in &changes.merged {
+            self.remove_descendants(key, &mut removed);
+            Arc::make_mut(&mut self.leaves).insert(key);
+        }
+        for key in &changes.split {
+            Arc::make_mut(&mut self.leaves).remove(key);
+        }
+
+        // The added nodes are inserted while descending from their roots, through their ancestors.
+        let added: HashSet<_> = changes.added.iter().copied().collect();
+        let mut paths = HashSet::default();
+        for &key in &changes.added {
+            let mut key = key;
+            while paths.insert(key) && key.level < root_level {
+                key = NodeKey::new(key.level + 1, key.coordinates >> 1);
+            }
+        }
+        let root_keys: Vec<_> = paths
+            .iter()
+            .copied()
+            .filter(|key| key.level == root_level)
+            .collect();
+
+        let Self { tree, leaves, .. } = self;
+        let leaves = Arc::make_mut(leaves);
+        for root_key in root_keys {
+            tree.fill_tree_from_root(root_key, 0, |key, entry| {
+                if !paths.contains(&key) {
+                    return VisitCommand::SkipDescendants;
+                }
+                if let NodeEntry::Vacant(v) = entry {
+                    v.insert(T::create(key));
+                    // A node that was split when it was added got all its children added as well.
+                    let first_child = NodeKey::new(key.level.max(1) - 1, key.coordinates << 1);
+                    if key.level == 0 || !added.contains(&first_child) {
+                        leaves.insert(key);
+                    }
+                }
+                VisitCommand::Continue
+            });
+        }
+    }
+
+    fn fill_tree(
+        &mut self,
+        root_key: NodeKey<IVec3>,
+        mut can_subdivide: impl FnMut(NodeKey<IVec3>, bool) -> bool,
+        mut may_change: impl FnMut(NodeKey<IVec3>) -> bool,
+        changes: &mut LodChanges,
+    ) {
+        let Self { tree, leaves, .. } = self;
+        let leaves = Arc::make_mut(leaves);
+
+        tree.fill_tree_from_root(root_key, 0, |key, entry| {
+            let occupied = matches!(entry, NodeEntry::Occupied(_));
+            if occupied && !may_change(key) {
+                return VisitCommand::SkipDescendants;
+            }
+
+            let split = occupied && !leaves.contains(&key);
+            let subdivide = key.level > 0 && can_subdivide(key, split);
+
+            match entry {
+                NodeEntry::Occupied(_) => {
+                    if subdivide {
+                        // A leaf that is split gets its children inserted while descending.
+                        if leaves.remove(&key) {
+                            changes.split.push(key);
+                        }
+                    } else if !leaves.contains(&key) {
+                        changes.merged.push(key);
+                    }
+                }
+                NodeEntry::Vacant(v) => {
+                    v.insert(T::create(key));
+                    changes.added.push(key);
+                    if !subdivide {
+                        leaves.insert(key);
+                    }
+                }
+            }
+
+            if subdivide {
+                VisitCommand::Continue
+            } else {
+                VisitCommand::SkipDescendants
+            }
+        });
+    }
+
+    /// Replaces the tree by an empty one of the given height, returning the keys of all the nodes
+    /// that were dropped.
+    pub fn reset(&mut self, height: Level) -> Vec<NodeKey<IVec3>> {
+        let mut dropped = Vec::new();
+        let root_keys: Vec<_> = self
+            .tree
+            .iter_roots()
+            .map(|(root_key, _)| *root_key)
+            .collect();
+        for root_key in root_keys {
+            let relation = ChildRelation {
+                child: root_key,
+                parent: None,
+            };
+            self.remove_tree(&relation, &mut dropped);
+        }
+
+        *self = Self::new(height);
+        dropped
+    }
+
+    /// Removes every descendant of `key` from the tree, turning it into a leaf. The keys of the
+    /// removed nodes are pushed to `removed`.
+    fn remove_descendants(&mut self, key: NodeKey<IVec3>, removed: &mut Vec<NodeKey<IVec3>>) {
+        let Some(ptr) = self.tree.find_node(key) else {
+            return;
+        };
+
+        let mut children = Vec::new();
+        self.tree.visit_children_with_coordinates(
+            ptr,
+            key.coordinates,
+            |child_ptr, child_coords| {
+                children.push(NodeKey::new(child_ptr.level(), child_coords));
+            },
+        );
+
+        for child in children {
+            let relation = ChildRelation {
+                child,
+                parent: Some(grid_tree::Parent {
+                    ptr,
+                    coordinates: key.coordinates,
+                    child_index: OctreeShapeI32::linearize_child(
+                        child.coordinates - (key.coordinates << 1),
+                    ),
+                }),
+            };
+            self.remove_tree(&relation, removed);
+        }
+    }
+
+    /// Removes `relation.child` and all of its descendants from the tree. The keys of the removed
+    /// nodes are pushed to `removed`.
+    fn remove_tree(&mut self, relation: &ChildRelation<IVec3>, removed: &mut Vec<NodeKey<IVec3>>) {
+        let leaves = Arc::make_mut(&mut self.leaves);
+        self.tree.remove_tree(relation, 0, |removed_key, payload| {
+            leaves.remove(&removed_key);
+            payload.remove(removed_key);
+            removed.push(removed_key);
+        });
+    }
+}
+
+/// The key of `leaves` containing `position`, if any.
+pub fn find_leaf(
+    leaves: &HashSet<NodeKey<IVec3>>,
+    root_level: Level,
+    position: Vec3,
+) -> Option<NodeKey<IVec3>> {
+    let coordinates = position.floor().as_ivec3();
+    (0..=root_level)
+        .map(|level| NodeKey::new(level, coordinates >> level as i32))
+        .find(|key| leaves.contains(key))
+}
+
+impl<T: NodePayload> Deref for Octree<T> {
+    type Target = OctreeI32<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tree
+    }
+}
+
+/// The nodes touched by a call to [`Octree::update`].
+#[derive(Debug, Default)]
+pub struct LodChanges {
+    pub added: Vec<NodeKey<IVec3>>,
+    pub removed: Vec<NodeKey<IVec3>>,
+    /// Former leaves that got children.
+    pub split: Vec<NodeKey<IVec3>>,
+    /// Former parents whose descendants were removed.
+    pub merged: Vec<NodeKey<IVec3>>,
+}
+
+impl LodChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.split.is_empty()
+            && self.merged.is_empty()
+    }
+}
+
+/// A node of the LOD octree along with its world-space bounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LodNode {
+    pub key: NodeKey<IVec3>,
+    pub level: Level,
+    pub min: IVec3,
+    pub max: IVec3,
+}
+
+impl LodNode {
+    pub fn new(key: NodeKey<IVec3>) -> Self {
+        let size = 1 << key.level;
+        let min = key.coordinates * size;
+        Self {
+            key,
+            level: key.level,
+            min,
+            max: min + IVec3::splat(size),
+        }
+    }
+}
+
+/// Sent when a node is inserted in the octree.
+#[derive(Clone, Copy, Debug, Deref)]
+pub struct LodNodeAdded(pub LodNode);
+
+/// Sent when a node is removed from the octree, because its parent got merged or its root isn't
+/// needed anymore.
+#[derive(Clone, Copy, Debug, Deref)]
+pub struct LodNodeRemoved(pub LodNode);
+
+/// Sent when a leaf gets children.
+#[derive(Clone, Copy, Debug, Deref)]
+pub struct LodNodeSplit(pub LodNode);
+
+/// Sent when a node loses its children and becomes a leaf again.
+#[derive(Clone, Copy, Debug, Deref)]
+pub struct LodNodeMerged(pub LodNode);
+
+/// Writers for all the node events.
+#[derive(SystemParam)]
+pub struct LodEventWriters<'w> {
+    added: EventWriter<'w, LodNodeAdded>,
+    removed: EventWriter<'w, LodNodeRemoved>,
+    split: EventWriter<'w, LodNodeSplit>,
+    merged: EventWriter<'w, LodNodeMerged>,
+}
+
+impl LodEventWriters<'_> {
+    /// Sends the event of each node in `changes`.
+    pub fn send(&mut self, changes: LodChanges) {
+        if changes.is_empty() {
+            return;
+        }
+
+        let to_node = |key: &NodeKey<IVec3>| LodNode::new(*key);
+        self.removed
+            .send_batch(changes.removed.iter().map(to_node).map(LodNodeRemoved));
+        self.merged
+            .send_batch(changes.merged.iter().map(to_node).map(LodNodeMerged));
+        self.split
+            .send_batch(changes.split.iter().map(to_node).map(LodNodeSplit));
+        self.added
+            .send_batch(changes.added.iter().map(to_node).map(LodNodeAdded));
+    }
+}
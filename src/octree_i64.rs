@@ -0,0 +1,200 @@
+use bevy::{math::DVec3, utils::HashSet};
+use grid_tree::Level;
+
+use crate::subdivision::LodRangeError;
+
+/// Height of the tallest tree the `i64` math supports. Its roots are 2^47 level 0 nodes wide, which
+/// still leaves room in `i64` for the observers within [`MAX_OBSERVER_COORDINATE`] and for a large
+/// detail.
+pub const MAX_OCTREE_HEIGHT_I64: Level = 48;
+
+/// Past this distance from the origin on any axis, an `f64` position can't tell neighboring level
+/// 0 nodes apart anymore.
+pub const MAX_OBSERVER_COORDINATE: f64 = (1u64 << 52) as f64;
+
+/// The greatest detail a tree of `height` supports, such that every subdivision test done by
+/// [`root_keys`] and below the roots it returns fits in `i64`, for observers within
+/// [`MAX_OBSERVER_COORDINATE`].
+pub fn max_detail(height: Level) -> Result<i32, LodRangeError> {
+    if !(1..=MAX_OCTREE_HEIGHT_I64).contains(&height) {
+        return Err(LodRangeError::UnsupportedHeight {
+            height,
+            max_height: MAX_OCTREE_HEIGHT_I64,
+        });
+    }
+
+    // In the half level 0 nodes the subdivision test works in, the observers are within 2^53 of
+    // the origin. The search only tests the neighbors of subdivided roots, so the grown bounds it
+    // computes all end less than `detail + 3` root widths from an observer.
+    let root_width = 1i64 << height;
+    let max_detail = (i64::MAX - (1 << 53)) / root_width - 3;
+    Ok(i32::try_from(max_detail).unwrap_or(i32::MAX))
+}
+
+/// Checks that a tree of `height` can be refined with `detail`.
+pub fn check_lod_range(height: Level, detail: i32) -> Result<(), LodRangeError> {
+    let max_detail = max_detail(height)?;
+    if !(0..=max_detail).contains(&detail) {
+        return Err(LodRangeError::UnsupportedDetail {
+            detail,
+            height,
+            max_detail,
+        });
+    }
+    Ok(())
+}
+
+/// A node of a tree with `i64` coordinates, in units of its own size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeKeyI64 {
+    pub level: Level,
+    pub coordinates: [i64; 3],
+}
+
+impl NodeKeyI64 {
+    pub fn new(level: Level, coordinates: [i64; 3]) -> Self {
+        Self { level, coordinates }
+    }
+
+    /// The key at `level`, which must not be lower than this one, containing this node.
+    pub fn ancestor(self, level: Level) -> Self {
+        let shift = level - self.level;
+        Self::new(level, self.coordinates.map(|c| c >> shift))
+    }
+
+    pub fn children(self) -> impl Iterator<Item = Self> {
+        (0..8).map(move |i| {
+            let offset = [i & 1, (i >> 1) & 1, (i >> 2) & 1];
+            let mut coordinates = self.coordinates;
+            for (c, offset) in coordinates.iter_mut().zip(offset) {
+                *c = (*c << 1) + offset;
+            }
+            Self::new(self.level - 1, coordinates)
+        })
+    }
+
+    /// [`CanSubdivide::can_subdivide`](crate::subdivision::CanSubdivide::can_subdivide) with `i64`
+    /// coordinates, failing instead of overflowing.
+    pub fn checked_can_subdivide(
+        &self,
+        node_key: Self,
+        detail: i32,
+    ) -> Result<bool, LodRangeError> {
+        if node_key.level < self.level {
+            return Ok(false);
+        }
+
+        let overflow = || LodRangeError::Overflow {
+            target: *self,
+            node_key,
+            detail,
+        };
+
+        // Everything is doubled so that the bounds grown by an odd detail stay integers.
+        let level_difference = u32::from(node_key.level - self.level);
+        let half_width = 2i64.checked_pow(level_difference).ok_or_else(overflow)?;
+        let width = half_width.checked_mul(2).ok_or_else(overflow)?;
+        let margin = half_width
+            .checked_mul(i64::from(detail))
+            .ok_or_else(overflow)?;
+
+        for (target, node) in self.coordinates.into_iter().zip(node_key.coordinates) {
+            let node_min = node.checked_mul(width).ok_or_else(overflow)?;
+            let min = node_min.checked_sub(margin).ok_or_else(overflow)?;
+            let max = node_min
+                .checked_add(width)
+                .and_then(|max| max.checked_add(margin))
+                .ok_or_else(overflow)?;
+            let local = target.checked_mul(2).ok_or_else(overflow)?;
+
+            if local < min || local >= max {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// A point a tree with `i64` coordinates is refined around, like a
+/// [`LodObserver`](crate::subdivision::LodObserver) with the
+/// [`ChebyshevBox`](crate::subdivision::ChebyshevBox) strategy.
+#[derive(Clone, Copy, Debug)]
+pub struct LodObserverI64 {
+    pub position: DVec3,
+    pub detail: i32,
+    /// The finest level this observer subdivides the tree to.
+    pub min_level: Level,
+}
+
+impl LodObserverI64 {
+    /// The level 0 key containing the observer.
+    pub fn key(&self) -> Result<NodeKeyI64, LodRangeError> {
+        let in_range = |c: f64| c.is_finite() && c.abs() <= MAX_OBSERVER_COORDINATE;
+        if !self.position.to_array().into_iter().all(in_range) {
+            return Err(LodRangeError::ObserverOutOfRange {
+                position: self.position,
+            });
+        }
+        Ok(NodeKeyI64::new(
+            0,
+            self.position.floor().to_array().map(|c| c as i64),
+        ))
+    }
+
+    pub fn can_subdivide(&self, node_key: NodeKeyI64) -> Result<bool, LodRangeError> {
+        if node_key.level <= self.min_level {
+            return Ok(false);
+        }
+        self.key()?.checked_can_subdivide(node_key, self.detail)
+    }
+}
+
+/// The roots of a tree of `height` around `observers`, like [`refine`](crate::refine::refine)
+/// finds them: the root containing each observer, plus the roots one of them subdivides, found by
+/// spreading out from the observer's root. Their number grows with the cube of the detail.
+pub fn root_keys(
+    height: Level,
+    observers: &[LodObserverI64],
+) -> Result<HashSet<NodeKeyI64>, LodRangeError> {
+    let mut observer_keys = Vec::with_capacity(observers.len());
+    for observer in observers {
+        check_lod_range(height, observer.detail)?;
+        observer_keys.push(observer.key()?);
+    }
+
+    let root_level = height - 1;
+    let can_subdivide = |root_key: NodeKeyI64| -> Result<bool, LodRangeError> {
+        for (observer, key) in observers.iter().zip(&observer_keys) {
+            if root_level > observer.min_level
+                && key.checked_can_subdivide(root_key, observer.detail)?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    };
+
+    let mut root_keys = HashSet::default();
+    let mut to_visit = Vec::new();
+    for key in &observer_keys {
+        let observer_root = key.ancestor(root_level);
+        if root_keys.insert(observer_root) && can_subdivide(observer_root)? {
+            to_visit.push(observer_root);
+        }
+    }
+    while let Some(root_key) = to_visit.pop() {
+        let [r_x, r_y, r_z] = root_key.coordinates;
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let neighbor = NodeKeyI64::new(root_level, [r_x + x, r_y + y, r_z + z]);
+                    if !root_keys.contains(&neighbor) && can_subdivide(neighbor)? {
+                        root_keys.insert(neighbor);
+                        to_visit.push(neighbor);
+                    }
+                }
+            }
+        }
+    }
+    Ok(root_keys)
+}
@@ -0,0 +1,259 @@
+use std::{mem, sync::Arc};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashSet,
+};
+use futures_lite::future;
+use grid_tree::{Level, NodeKey};
+
+use crate::{
+    balance::{balanced_interior, Adjacency},
+    octree::{LodChanges, NodePayload, Octree},
+    subdivision::{LodObserver, SubdivisionStrategy},
+};
+
+/// The settings a refinement of the tree depends on, copied so that it can run on another thread.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RefineSettings {
+    pub height: Level,
+    /// See [`LodObserver::keep_subdivided`].
+    pub merge_margin: i32,
+    /// Balancing looks at the whole tree, so refinements with it don't scale with how much the
+    /// observers moved anymore.
+    pub balance: Option<Adjacency>,
+}
+
+/// The observers and settings a tree was last refined for.
+#[derive(Clone, Debug)]
+pub(crate) struct Refinement {
+    observers: Vec<LodObserver>,
+    settings: RefineSettings,
+}
+
+/// Refines `tree` around `observers`, rebuilding it first if its height changed. Only the nodes
+/// the observers that moved, came or went subdivide before or after are tested again, unless the
+/// settings changed since the last refinement.
+pub fn refine<T: NodePayload>(
+    tree: &mut Octree<T>,
+    observers: &[LodObserver],
+    strategy: &dyn SubdivisionStrategy,
+    settings: RefineSettings,
+) -> LodChanges {
+    // The height can't change in place, so the old tree is dropped entirely and the new one is
+    // filled from scratch below.
+    let dropped = if tree.root_level() + 1 != settings.height {
+        tree.reset(settings.height)
+    } else {
+        Vec::new()
+    };
+
+    // A node is subdivided as soon as one of the observers needs it, and stays so until all of
+    // them are past the merge margin.
+    let can_subdivide = |key, split| {
+        observers.iter().any(|observer| {
+            if split {
+                observer.keep_subdivided(strategy, key, settings.merge_margin)
+            } else {
+                observer.can_subdivide(strategy, key)
+            }
+        })
+    };
+
+    // The roots tile space around the observers: each observer keeps the root containing it, plus
+    // the roots it wants to subdivide, found by spreading out from its own root. With no observer
+    // at all, the tree is empty.
+    let root_level = tree.root_level();
+    let mut root_keys = HashSet::default();
+    let mut to_visit = Vec::new();
+    for observer in observers {
+        let observer_root =
+            NodeKey::new(root_level, observer.key().coordinates >> root_level as i32);
+        if root_keys.insert(observer_root)
+            && can_subdivide(observer_root, tree.is_split(observer_root))
+        {
+            to_visit.push(observer_root);
+        }
+    }
+    while let Some(root_key) = to_visit.pop() {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let neighbor =
+                        NodeKey::new(root_level, root_key.coordinates + IVec3::new(x, y, z));
+                    if !root_keys.contains(&neighbor)
+                        && can_subdivide(neighbor, tree.is_split(neighbor))
+                    {
+                        root_keys.insert(neighbor);
+                        to_visit.push(neighbor);
+                    }
+                }
+            }
+        }
+    }
+
+    // Since the parents of the nodes an observer subdivides are subdivided as well, the other
+    // observers can't change anything below a node none of the changed ones subdivides.
+    let changed = tree
+        .refinement
+        .take()
+        .filter(|refinement| refinement.settings == settings)
+        .map(|refinement| changed_observers(&refinement.observers, observers));
+    let may_change = |key| match &changed {
+        Some(changed) => changed.iter().any(|observer| {
+            observer.can_subdivide(strategy, key)
+                || observer.keep_subdivided(strategy, key, settings.merge_margin)
+        }),
+        None => true,
+    };
+
+    let mut changes = match settings.balance {
+        Some(adjacency) => {
+            let interior = balanced_interior(
+                &root_keys,
+                root_level,
+                |key| can_subdivide(key, tree.is_split(key)),
+                adjacency,
+            );
+            tree.update(&root_keys, |key, _| interior.contains(&key), |_| true)
+        }
+        None => tree.update(&root_keys, can_subdivide, may_change),
+    };
+    changes.removed.extend(dropped);
+    tree.refinement = Some(Refinement {
+        observers: observers.to_vec(),
+        settings,
+    });
+    changes
+}
+
+/// The observers that are in only one of `previous` and `current`.
+fn changed_observers(previous: &[LodObserver], current: &[LodObserver]) -> Vec<LodObserver> {
+    let mut unmatched = previous.to_vec();
+    let mut changed = Vec::new();
+    for observer in current {
+        match unmatched.iter().position(|previous| previous == observer) {
+            Some(i) => {
+                unmatched.swap_remove(i);
+            }
+            None => changed.push(*observer),
+        }
+    }
+    changed.extend(unmatched);
+    changed
+}
+
+/// A tree of `height` with `leaves` and their ancestors, as last refined for `refinement`.
+fn tree_with_leaves(
+    height: Level,
+    leaves: &HashSet<NodeKey<IVec3>>,
+    refinement: Option<Refinement>,
+) -> Octree {
+    let root_level = height - 1;
+    let mut root_keys = HashSet::default();
+    let mut split_nodes = HashSet::default();
+    for &leaf in leaves {
+        root_keys.insert(NodeKey::new(
+            root_level,
+            leaf.coordinates >> (root_level - leaf.level) as i32,
+        ));
+        let mut key = leaf;
+        while key.level < root_level {
+            key = NodeKey::new(key.level + 1, key.coordinates >> 1);
+            if !split_nodes.insert(key) {
+                break;
+            }
+        }
+    }
+
+    let mut tree = Octree::new(height);
+    tree.update(&root_keys, |key, _| split_nodes.contains(&key), |_| true);
+    tree.refinement = refinement;
+    tree
+}
+
+/// Refines a copy of the tree on [`AsyncComputeTaskPool`], so that deep trees don't stall the
+/// frame. The copy has no payloads: [`LodWorker::poll`] makes the changes the job found to the
+/// [`Octree`] resource, so the main thread only visits the nodes that changed and the payloads are
+/// created and removed once, in the resource's tree.
+#[derive(Resource)]
+pub struct LodWorker {
+    /// The copy, with the same nodes as the resource's tree unless `stale` is set. Taken while a
+    /// job runs, and dropped with it if the job is cancelled.
+    back: Option<Octree>,
+    job: Option<Task<(Octree, LodChanges)>>,
+    /// Set when the copy may not match the resource's tree anymore, in which case the next job
+    /// rebuilds it from the resource's leaves first.
+    stale: bool,
+}
+
+impl Default for LodWorker {
+    fn default() -> Self {
+        Self {
+            back: None,
+            job: None,
+            stale: true,
+        }
+    }
+}
+
+impl LodWorker {
+    pub fn is_busy(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Tells the worker that `front` was refined directly.
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// Drops the running job, if any, whose result would be outdated by the time it is polled.
+    pub fn cancel(&mut self) {
+        if self.job.take().is_some() {
+            // The copy was moved into the job.
+            self.stale = true;
+        }
+    }
+
+    /// Starts refining the copy of `front`, which must not be busy already.
+    pub fn start<T: NodePayload>(
+        &mut self,
+        front: &Octree<T>,
+        observers: Vec<LodObserver>,
+        strategy: Arc<dyn SubdivisionStrategy>,
+        settings: RefineSettings,
+    ) {
+        assert!(!self.is_busy(), "the LOD worker is already busy");
+        let back = self.back.take();
+        // Only a handle to the leaves is taken here, the copy is rebuilt by the job.
+        let catch_up =
+            mem::take(&mut self.stale).then(|| (front.leaves_snapshot(), front.refinement.clone()));
+        let height = front.root_level() + 1;
+
+        self.job = Some(AsyncComputeTaskPool::get().spawn(async move {
+            let mut tree = match catch_up {
+                Some((leaves, refinement)) => tree_with_leaves(height, &leaves, refinement),
+                None => back.expect("the copy is kept while it matches the resource's tree"),
+            };
+            let changes = refine(&mut tree, &observers, strategy.as_ref(), settings);
+            (tree, changes)
+        }));
+    }
+
+    /// If the running job is done, makes the changes it found to `front` and returns them.
+    pub fn poll<T: NodePayload>(&mut self, front: &mut Octree<T>) -> Option<LodChanges> {
+        let job = self.job.as_mut()?;
+        let (tree, changes) = future::block_on(future::poll_once(job))?;
+        self.job = None;
+
+        // The nodes of the old height are all part of `changes.removed` already.
+        if tree.root_level() != front.root_level() {
+            front.reset(tree.root_level() + 1);
+        }
+        front.apply(&changes);
+        front.refinement = tree.refinement.clone();
+        self.back = Some(tree);
+        Some(changes)
+    }
+}
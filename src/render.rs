@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::{DebugLines, DebugShapes};
+use grid_tree::{NodePtr, VisitCommand};
+
+use crate::{
+    lod::OctreeSettings,
+    octree::{LodNode, NodePayload, Octree},
+};
+
+/// Draws the transition faces and the nodes of the tree, as enabled in [`OctreeSettings`].
+pub fn render<T: NodePayload>(
+    mut shapes: ResMut<DebugShapes>,
+    mut lines: ResMut<DebugLines>,
+    tree: Res<Octree<T>>,
+    settings: Res<OctreeSettings>,
+) {
+    if settings.draw_transitions {
+        for leaf_key in tree.leaves() {
+            let leaf = LodNode::new(leaf_key);
+            let (min, max) = (leaf.min.as_vec3(), leaf.max.as_vec3());
+
+            for axis in 0..3 {
+                for positive in [false, true] {
+                    let mut face_min = min;
+                    let mut face_max = max;
+                    let mut outside = (min + max) / 2.0;
+                    if positive {
+                        face_min[axis] = max[axis];
+                        outside[axis] = max[axis] + 0.5;
+                    } else {
+                        face_max[axis] = min[axis];
+                        outside[axis] = min[axis] - 0.5;
+                    }
+
+                    // Drawn from the finer side only, which is the one stitching the meshes.
+                    let coarser_neighbor = tree
+                        .leaf_at(outside)
+                        .is_some_and(|neighbor| neighbor.level > leaf.level);
+                    if !coarser_neighbor {
+                        continue;
+                    }
+
+                    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                    let mut corners = [face_min; 4];
+                    corners[1][u] = face_max[u];
+                    corners[2][u] = face_max[u];
+                    corners[2][v] = face_max[v];
+                    corners[3][v] = face_max[v];
+                    for (i, &corner) in corners.iter().enumerate() {
+                        lines.line_colored(
+                            corner,
+                            corners[(i + 1) % 4],
+                            0.0,
+                            settings.transition_color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    if !settings.draw_nodes {
+        return;
+    }
+
+    tree.iter_roots()
+        .map(|(root_key, root_node)| (root_key, NodePtr::new(root_key.level, root_node.self_ptr)))
+        .for_each(|(root_key, root_ptr)| {
+            tree.visit_tree_depth_first(
+                root_ptr,
+                root_key.coordinates,
+                0,
+                |child_ptr, child_coords| {
+                    let scale_factor = 2i32.pow(child_ptr.level() as u32);
+                    let child_min = child_coords * scale_factor;
+                    let child_max = child_min + IVec3::splat(scale_factor);
+
+                    let color = if child_ptr.level() == root_key.level {
+                        settings.root_color
+                    } else if child_ptr.level() == 0 {
+                        settings.leaf_color
+                    } else {
+                        settings.node_color
+                    };
+
+                    shapes
+                        .cuboid()
+                        .min_max(child_min.as_vec3(), child_max.as_vec3())
+                        .color(color);
+
+                    VisitCommand::Continue
+                },
+            );
+        });
+}
@@ -0,0 +1,378 @@
+use std::{fmt, sync::Arc};
+
+use bevy::{math::DVec3, prelude::*};
+use grid_tree::{Level, NodeKey};
+
+use crate::{
+    lod::MAX_OCTREE_HEIGHT,
+    octree_i64::{NodeKeyI64, MAX_OBSERVER_COORDINATE},
+};
+
+pub trait CanSubdivide {
+    fn can_subdivide(&self, node: Self, detail: i32) -> bool;
+}
+
+impl CanSubdivide for NodeKey<IVec3> {
+    /// Adapted from https://github.com/Dimev/lodtree
+    fn can_subdivide(&self, node_key: Self, detail: i32) -> bool {
+        if node_key.level < self.level {
+            return false;
+        }
+
+        let level_difference = node_key.level - self.level;
+        let [s_x, s_y, s_z] = self.coordinates.to_array();
+        let [n_x, n_y, n_z] = node_key.coordinates.to_array();
+
+        // minimum corner of the bounding box
+        let min = (
+            (n_x << (level_difference + 1))
+                .saturating_sub(((detail + 1) << level_difference) - (1 << level_difference)),
+            (n_y << (level_difference + 1))
+                .saturating_sub(((detail + 1) << level_difference) - (1 << level_difference)),
+            (n_z << (level_difference + 1))
+                .saturating_sub(((detail + 1) << level_difference) - (1 << level_difference)),
+        );
+
+        // max as well
+        let max = (
+            (n_x << (level_difference + 1))
+                .saturating_add(((detail + 1) << level_difference) + (1 << level_difference)),
+            (n_y << (level_difference + 1))
+                .saturating_add(((detail + 1) << level_difference) + (1 << level_difference)),
+            (n_z << (level_difference + 1))
+                .saturating_add(((detail + 1) << level_difference) + (1 << level_difference)),
+        );
+
+        // local position of the target
+        let local = (s_x << 1, s_y << 1, s_z << 1);
+
+        // check if the target is inside of the bounding box
+        local.0 >= min.0
+            && local.0 < max.0
+            && local.1 >= min.1
+            && local.1 < max.1
+            && local.2 >= min.2
+            && local.2 < max.2
+    }
+}
+
+/// The greatest detail a tree of `height` supports, such that [`CanSubdivide::can_subdivide`]
+/// doesn't overflow `i32` when testing its roots for a level 0 target.
+pub fn max_detail(height: Level) -> Result<i32, LodRangeError> {
+    if !(1..=MAX_OCTREE_HEIGHT).contains(&height) {
+        return Err(LodRangeError::UnsupportedHeight {
+            height,
+            max_height: MAX_OCTREE_HEIGHT,
+        });
+    }
+
+    // The bounds of a root are grown by `(detail + 2) << (height - 1)` half level 0 nodes.
+    Ok((i32::MAX >> (height - 1)) - 2)
+}
+
+/// Checks that a tree of `height` can be refined with `detail`.
+pub fn check_lod_range(height: Level, detail: i32) -> Result<(), LodRangeError> {
+    let max_detail = max_detail(height)?;
+    if !(0..=max_detail).contains(&detail) {
+        return Err(LodRangeError::UnsupportedDetail {
+            detail,
+            height,
+            max_detail,
+        });
+    }
+    Ok(())
+}
+
+/// Why a tree can't be refined with a height, a detail or an observer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LodRangeError {
+    /// The height is 0 or greater than the tallest one the coordinates support,
+    /// [`MAX_OCTREE_HEIGHT`] or [`MAX_OCTREE_HEIGHT_I64`](crate::MAX_OCTREE_HEIGHT_I64).
+    UnsupportedHeight { height: Level, max_height: Level },
+    /// The detail is negative, or so large that growing the roots of a tree of this height by it
+    /// overflows the coordinates.
+    UnsupportedDetail {
+        detail: i32,
+        height: Level,
+        max_detail: i32,
+    },
+    /// The position of an observer isn't finite or is past [`MAX_OBSERVER_COORDINATE`]. Only
+    /// checked by the `i64` math.
+    ObserverOutOfRange { position: DVec3 },
+    /// An intermediate value of [`NodeKeyI64::checked_can_subdivide`] doesn't fit in `i64`.
+    Overflow {
+        target: NodeKeyI64,
+        node_key: NodeKeyI64,
+        detail: i32,
+    },
+}
+
+impl fmt::Display for LodRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedHeight { height, max_height } => write!(
+                f,
+                "unsupported octree height {height}, expected 1 to {max_height}"
+            ),
+            Self::UnsupportedDetail {
+                detail,
+                height,
+                max_detail,
+            } => write!(
+                f,
+                "unsupported detail {detail} for an octree of height {height}, expected 0 to \
+                 {max_detail}"
+            ),
+            Self::ObserverOutOfRange { position } => write!(
+                f,
+                "observer at {position} is out of range, coordinates must be finite and within \
+                 ±{MAX_OBSERVER_COORDINATE}"
+            ),
+            Self::Overflow {
+                target,
+                node_key,
+                detail,
+            } => write!(
+                f,
+                "overflow testing whether {node_key:?} can subdivide for {target:?} with detail \
+                 {detail}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LodRangeError {}
+
+/// A point the tree is refined around, as seen by a [`SubdivisionStrategy`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodObserver {
+    pub position: Vec3,
+    /// The number of nodes around the observer that are refined at each level. How it is used
+    /// depends on the strategy.
+    pub detail: i32,
+    /// The finest level this observer subdivides the tree to.
+    pub min_level: Level,
+    /// Set for the observers that are cameras with a perspective projection: something of length
+    /// `l` at a distance `d` from the observer covers about `l * projection_scale / d` pixels
+    /// vertically.
+    pub projection_scale: Option<f32>,
+}
+
+impl LodObserver {
+    /// The level 0 key containing the observer.
+    pub fn key(&self) -> NodeKey<IVec3> {
+        // Flooring rather than truncating so that keys keep their size on the negative side.
+        NodeKey::new(0, self.position.floor().as_ivec3())
+    }
+
+    pub fn can_subdivide(
+        &self,
+        strategy: &dyn SubdivisionStrategy,
+        node_key: NodeKey<IVec3>,
+    ) -> bool {
+        node_key.level > self.min_level && strategy.can_subdivide(self, node_key)
+    }
+
+    /// Like [`LodObserver::can_subdivide`], for a node that is already subdivided. See
+    /// [`SubdivisionStrategy::keep_subdivided`].
+    pub fn keep_subdivided(
+        &self,
+        strategy: &dyn SubdivisionStrategy,
+        node_key: NodeKey<IVec3>,
+        margin: i32,
+    ) -> bool {
+        node_key.level > self.min_level && strategy.keep_subdivided(self, node_key, margin)
+    }
+}
+
+/// Decides which nodes of the tree get children.
+pub trait SubdivisionStrategy: Send + Sync + 'static {
+    /// Whether `node_key` should be subdivided for `observer`. The tree is refined wherever this
+    /// returns true for at least one observer, which must stop being the case past some distance
+    /// from it: the roots of the tree are found by spreading out from the observer's root until
+    /// they can't be subdivided anymore. It must also be true for the parent of every node it is
+    /// true for, since a refinement only descends into the nodes the moved observers subdivide.
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool;
+
+    /// Whether `node_key`, which is already subdivided, should stay so. This should pass for the
+    /// observers `margin` half node widths further away than the ones [`Self::can_subdivide`]
+    /// accepts, so that an observer moving back and forth across the boundary doesn't split and
+    /// merge the node every time. By default the observer's detail is raised by `margin`.
+    fn keep_subdivided(
+        &self,
+        observer: &LodObserver,
+        node_key: NodeKey<IVec3>,
+        margin: i32,
+    ) -> bool {
+        let observer = LodObserver {
+            detail: observer.detail + margin,
+            ..*observer
+        };
+        self.can_subdivide(&observer, node_key)
+    }
+
+    /// Whether the active 3D cameras are observers on top of the targets.
+    fn uses_cameras(&self) -> bool {
+        false
+    }
+}
+
+/// The strategy used by [`update_octree`](crate::lod::update_octree).
+#[derive(Resource, Deref)]
+pub struct LodStrategy(pub Arc<dyn SubdivisionStrategy>);
+
+impl Default for LodStrategy {
+    fn default() -> Self {
+        Self(Arc::new(ChebyshevBox))
+    }
+}
+
+/// Subdivides the nodes whose bounds, grown by `detail / 2` node widths on each side, contain the
+/// observer. This is [`CanSubdivide::can_subdivide`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChebyshevBox;
+
+impl SubdivisionStrategy for ChebyshevBox {
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool {
+        observer.key().can_subdivide(node_key, observer.detail)
+    }
+}
+
+/// Like [`ChebyshevBox`], but the margin around the node is measured with the Euclidean distance,
+/// which rounds the corners of the refined region.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EuclideanSphere;
+
+impl SubdivisionStrategy for EuclideanSphere {
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool {
+        let (min, max) = node_min_max(node_key);
+        let margin = observer.detail as f32 * node_size(node_key) / 2.0;
+        observer
+            .position
+            .distance(observer.position.clamp(min, max))
+            <= margin
+    }
+}
+
+/// Subdivides the nodes whose size is greater than `ratio` times their distance to the observer.
+/// Ignores [`LodObserver::detail`].
+#[derive(Clone, Copy, Debug)]
+pub struct SizeRatio {
+    pub ratio: f32,
+}
+
+impl Default for SizeRatio {
+    fn default() -> Self {
+        Self { ratio: 1.0 }
+    }
+}
+
+impl SubdivisionStrategy for SizeRatio {
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool {
+        let (min, max) = node_min_max(node_key);
+        let distance = observer.position.distance((min + max) / 2.0);
+        node_size(node_key) > self.ratio * distance
+    }
+
+    fn keep_subdivided(
+        &self,
+        observer: &LodObserver,
+        node_key: NodeKey<IVec3>,
+        margin: i32,
+    ) -> bool {
+        let (min, max) = node_min_max(node_key);
+        let distance = observer.position.distance((min + max) / 2.0);
+        node_size(node_key) > self.ratio * (distance - margin_distance(node_key, margin))
+    }
+}
+
+/// Never subdivides the nodes at or below `min_level`, whatever `inner` says.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MinLevel<S> {
+    pub min_level: Level,
+    pub inner: S,
+}
+
+impl<S: SubdivisionStrategy> SubdivisionStrategy for MinLevel<S> {
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool {
+        node_key.level > self.min_level && self.inner.can_subdivide(observer, node_key)
+    }
+
+    fn keep_subdivided(
+        &self,
+        observer: &LodObserver,
+        node_key: NodeKey<IVec3>,
+        margin: i32,
+    ) -> bool {
+        node_key.level > self.min_level && self.inner.keep_subdivided(observer, node_key, margin)
+    }
+
+    fn uses_cameras(&self) -> bool {
+        self.inner.uses_cameras()
+    }
+}
+
+/// Subdivides the nodes that cover more than `threshold` pixels on the screen of a camera, using
+/// the distance from the camera to the closest point of the node. Observers without a
+/// [`LodObserver::projection_scale`] never subdivide anything.
+#[derive(Clone, Copy, Debug)]
+pub struct ScreenSpaceError {
+    pub threshold: f32,
+}
+
+impl Default for ScreenSpaceError {
+    fn default() -> Self {
+        Self { threshold: 128.0 }
+    }
+}
+
+impl SubdivisionStrategy for ScreenSpaceError {
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool {
+        let Some(projection_scale) = observer.projection_scale else {
+            return false;
+        };
+
+        let (min, max) = node_min_max(node_key);
+        let distance = observer
+            .position
+            .distance(observer.position.clamp(min, max));
+        node_size(node_key) * projection_scale > self.threshold * distance
+    }
+
+    fn keep_subdivided(
+        &self,
+        observer: &LodObserver,
+        node_key: NodeKey<IVec3>,
+        margin: i32,
+    ) -> bool {
+        let Some(projection_scale) = observer.projection_scale else {
+            return false;
+        };
+
+        let (min, max) = node_min_max(node_key);
+        let distance = observer
+            .position
+            .distance(observer.position.clamp(min, max))
+            - margin_distance(node_key, margin);
+        node_size(node_key) * projection_scale > self.threshold * distance
+    }
+
+    fn uses_cameras(&self) -> bool {
+        true
+    }
+}
+
+fn node_size(node_key: NodeKey<IVec3>) -> f32 {
+    (1 << node_key.level) as f32
+}
+
+/// The length of `margin` half node widths.
+fn margin_distance(node_key: NodeKey<IVec3>, margin: i32) -> f32 {
+    margin as f32 * node_size(node_key) / 2.0
+}
+
+fn node_min_max(node_key: NodeKey<IVec3>) -> (Vec3, Vec3) {
+    let size = node_size(node_key);
+    let min = node_key.coordinates.as_vec3() * size;
+    (min, min + Vec3::splat(size))
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use grid_tree::{Level, NodeKey};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+/// Number of cells along each axis of a chunk, whatever its level.
+pub const CHUNK_CELLS: u32 = 16;
+/// Number of samples along each axis of a chunk: the `CHUNK_CELLS + 1` corners of its cells, from
+/// its minimum to its maximum boundary, plus one sample of padding on the minimum side only, so
+/// that the meshes of neighboring chunks can be joined. Sample 0 is the padding.
+pub const CHUNK_SAMPLES: u32 = CHUNK_CELLS + 2;
+
+/// A signed distance field: negative inside the solid, positive outside.
+pub trait DensityGenerator: Send + Sync + 'static {
+    fn density(&self, position: Vec3) -> f32;
+}
+
+/// The generator sampled into each chunk.
+#[derive(Resource, Clone, Deref)]
+pub struct ChunkGenerator(pub Arc<dyn DensityGenerator>);
+
+/// Fractal noise terrain around the plane `y = ground_height`, with some spheres carved out of it.
+pub struct Terrain {
+    noise: Fbm<Perlin>,
+    pub ground_height: f32,
+    /// How far the noise moves the surface away from the ground plane.
+    pub amplitude: f32,
+    pub caves: Vec<Sphere>,
+}
+
+impl Terrain {
+    pub fn new(seed: u32, ground_height: f32, amplitude: f32) -> Self {
+        Self {
+            noise: Fbm::<Perlin>::new(seed)
+                .set_octaves(5)
+                .set_frequency(1.0 / amplitude as f64),
+            ground_height,
+            amplitude,
+            caves: Vec::new(),
+        }
+    }
+
+    /// The terrain of the examples for a tree of `height`: the ground through the middle of the
+    /// root, with a cave at its center.
+    pub fn for_height(height: Level) -> Self {
+        let root_length = (1 << (height - 1)) as f32;
+        let mut terrain = Self::new(0, root_length / 2.0, root_length / 8.0);
+        terrain.caves.push(Sphere {
+            center: Vec3::splat(root_length / 2.0),
+            radius: root_length / 6.0,
+        });
+        terrain
+    }
+}
+
+impl DensityGenerator for Terrain {
+    fn density(&self, position: Vec3) -> f32 {
+        let noise = self.noise.get(position.as_dvec3().to_array()) as f32;
+        let ground = position.y - self.ground_height + noise * self.amplitude;
+
+        self.caves
+            .iter()
+            .fold(ground, |density, cave| density.max(-cave.density(position)))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl DensityGenerator for Sphere {
+    fn density(&self, position: Vec3) -> f32 {
+        position.distance(self.center) - self.radius
+    }
+}
+
+/// The density samples of a chunk. Sample `(x, y, z)` is at `(x - 1, y - 1, z - 1) * step` from
+/// the chunk's minimum corner.
+#[derive(Component, Clone)]
+pub struct ChunkVoxels {
+    /// Shared with the meshing job of the chunk.
+    pub samples: Arc<[f32]>,
+    /// Distance between two samples, which doubles at each level so that every chunk has the same
+    /// number of samples.
+    pub step: f32,
+}
+
+impl ChunkVoxels {
+    pub fn sample(generator: &dyn DensityGenerator, key: NodeKey<IVec3>) -> Self {
+        let mut samples = Vec::with_capacity((CHUNK_SAMPLES as usize).pow(3));
+        for z in 0..CHUNK_SAMPLES {
+            for y in 0..CHUNK_SAMPLES {
+                for x in 0..CHUNK_SAMPLES {
+                    let position = sample_position(key, UVec3::new(x, y, z).as_ivec3());
+                    samples.push(generator.density(position));
+                }
+            }
+        }
+
+        Self {
+            samples: samples.into(),
+            step: chunk_step(key.level),
+        }
+    }
+
+    pub fn get(&self, sample: UVec3) -> f32 {
+        self.samples[linearize(sample)]
+    }
+
+    /// Whether every sample has the same sign, in which case the chunk has no surface.
+    pub fn is_uniform(&self) -> bool {
+        let inside = self.samples[0] < 0.0;
+        self.samples.iter().all(|&sample| (sample < 0.0) == inside)
+    }
+}
+
+/// Distance between two samples of a chunk at `level`.
+pub fn chunk_step(level: Level) -> f32 {
+    (1 << level) as f32 / CHUNK_CELLS as f32
+}
+
+/// World position of a sample of the chunk `key`, following the layout of [`ChunkVoxels`]. The
+/// sample may be outside of the chunk, so that the grid of a chunk can be extended to its
+/// neighbors.
+pub fn sample_position(key: NodeKey<IVec3>, sample: IVec3) -> Vec3 {
+    let min = (key.coordinates * (1 << key.level)).as_vec3();
+    min + (sample.as_vec3() - 1.0) * chunk_step(key.level)
+}
+
+pub fn linearize(sample: UVec3) -> usize {
+    (sample.x + CHUNK_SAMPLES * (sample.y + CHUNK_SAMPLES * sample.z)) as usize
+}
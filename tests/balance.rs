@@ -0,0 +1,67 @@
+//! Checks that the balancing pass leaves no neighboring leaves more than one level apart, and that
+//! the validator reports the ones it finds.
+
+mod common;
+
+use bevy::{prelude::*, utils::HashSet};
+use common::{refined_tree, CASES};
+use grid_tree::NodeKey;
+use octree_experiment::{
+    balance::{balanced_interior, find_balance_violations, Adjacency},
+    octree::Octree,
+};
+
+const ADJACENCIES: [Adjacency; 3] = [Adjacency::Face, Adjacency::Edge, Adjacency::Corner];
+
+#[test]
+fn balanced_trees_have_no_violations() {
+    for case in CASES {
+        for adjacency in ADJACENCIES {
+            let tree = refined_tree(case, Some(adjacency));
+            let violations = find_balance_violations(&tree, adjacency);
+            assert!(
+                violations.is_empty(),
+                "{}, {adjacency:?}: {} violations, e.g. {:?}",
+                case.name,
+                violations.len(),
+                violations[0]
+            );
+        }
+    }
+}
+
+/// A level 0 leaf in the corner of the root, next to level 2 leaves.
+#[test]
+fn reports_and_fixes_violations() {
+    let root_key = NodeKey::new(3, IVec3::ZERO);
+    let root_keys = HashSet::from_iter([root_key]);
+    let split_nodes = HashSet::from_iter([
+        root_key,
+        NodeKey::new(2, IVec3::ZERO),
+        NodeKey::new(1, IVec3::ONE),
+    ]);
+
+    for adjacency in ADJACENCIES {
+        let mut tree = Octree::<()>::new(4);
+        tree.update(&root_keys, |key, _| split_nodes.contains(&key), |_| true);
+        let violations = find_balance_violations(&tree, adjacency);
+        assert!(!violations.is_empty(), "{adjacency:?}");
+        for violation in violations {
+            assert_eq!(violation.leaf.level, 0);
+            assert_eq!(violation.coarse_neighbor.level, 2);
+        }
+
+        let interior = balanced_interior(
+            &root_keys,
+            root_key.level,
+            |key| split_nodes.contains(&key),
+            adjacency,
+        );
+        assert!(interior.is_superset(&split_nodes));
+        tree.update(&root_keys, |key, _| interior.contains(&key), |_| true);
+        assert!(
+            find_balance_violations(&tree, adjacency).is_empty(),
+            "{adjacency:?}"
+        );
+    }
+}
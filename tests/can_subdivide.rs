@@ -0,0 +1,261 @@
+//! Checks [`CanSubdivide::can_subdivide`] against a floating-point reference, and the properties
+//! the refinement of the tree relies on.
+
+use bevy::{prelude::*, utils::HashSet};
+use grid_tree::{Level, NodeKey};
+use octree_experiment::{
+    octree::Octree,
+    refine::{refine, RefineSettings},
+    subdivision::{check_lod_range, max_detail, CanSubdivide, ChebyshevBox, LodObserver},
+    LodRangeError, OctreeSettings, MAX_OCTREE_HEIGHT,
+};
+use proptest::prelude::*;
+
+const MAX_DETAIL: i32 = 16;
+
+/// The targets stay within a few roots of the origin of the tallest tree.
+const MAX_COORDINATE: i32 = 4 << (MAX_OCTREE_HEIGHT - 1);
+
+/// Refining is slow enough for the trees of the property tests to stay small.
+const MAX_REFINED_HEIGHT: Level = 6;
+
+/// Whether `target` is in the bounds of `node_key` grown by `detail / 2` node widths on each side,
+/// in units of the target's level. Like the bounds, the grown box includes its minimum corner but
+/// not its maximum one.
+fn reference(target: NodeKey<IVec3>, node_key: NodeKey<IVec3>, detail: i32) -> bool {
+    if node_key.level < target.level {
+        return false;
+    }
+
+    let width = (1u64 << (node_key.level - target.level)) as f64;
+    let margin = detail as f64 * width / 2.0;
+    let target = target.coordinates.as_dvec3();
+    let min = node_key.coordinates.as_dvec3() * width - margin;
+    let max = (node_key.coordinates + 1).as_dvec3() * width + margin;
+    target.cmpge(min).all() && target.cmplt(max).all()
+}
+
+fn coordinates(range: i32) -> impl Strategy<Value = IVec3> {
+    prop::array::uniform3(-range..range).prop_map(IVec3::from_array)
+}
+
+/// A target, a detail and a node of the same level or above, at most a few node widths away from
+/// the grown bounds so that both outcomes are common.
+fn target_detail_and_node() -> impl Strategy<Value = (NodeKey<IVec3>, i32, NodeKey<IVec3>)> {
+    (0..MAX_OCTREE_HEIGHT)
+        .prop_flat_map(|target_level| {
+            (
+                Just(target_level),
+                coordinates(MAX_COORDINATE),
+                0..=MAX_DETAIL,
+                target_level..MAX_OCTREE_HEIGHT,
+                coordinates(MAX_DETAIL / 2 + 2),
+            )
+        })
+        .prop_map(|(target_level, target, detail, node_level, offset)| {
+            let node = (target >> (node_level - target_level) as i32) + offset;
+            (
+                NodeKey::new(target_level, target),
+                detail,
+                NodeKey::new(node_level, node),
+            )
+        })
+}
+
+fn refine_tree(height: Level, observers: &[LodObserver]) -> Octree<()> {
+    let mut tree = Octree::new(height);
+    refine(
+        &mut tree,
+        observers,
+        &ChebyshevBox,
+        RefineSettings {
+            height,
+            merge_margin: 0,
+            balance: None,
+        },
+    );
+    tree
+}
+
+/// The nodes of `tree` under `root_key`, split or not.
+fn nodes_under(tree: &Octree<()>, root_key: NodeKey<IVec3>) -> HashSet<(NodeKey<IVec3>, bool)> {
+    let is_under = |key: &NodeKey<IVec3>| {
+        key.coordinates >> (root_key.level - key.level) as i32 == root_key.coordinates
+    };
+    tree.leaves()
+        .filter(is_under)
+        .map(|key| (key, false))
+        .chain(
+            tree.split_nodes()
+                .into_iter()
+                .filter(is_under)
+                .map(|key| (key, true)),
+        )
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn matches_reference((target, detail, node_key) in target_detail_and_node()) {
+        prop_assert_eq!(
+            target.can_subdivide(node_key, detail),
+            reference(target, node_key, detail)
+        );
+    }
+
+    #[test]
+    fn rejects_nodes_below_the_target(
+        target_level in 1..MAX_OCTREE_HEIGHT,
+        target in coordinates(MAX_COORDINATE),
+        detail in 0..=MAX_DETAIL,
+        levels_below in 1..MAX_OCTREE_HEIGHT,
+        node in coordinates(MAX_COORDINATE),
+    ) {
+        let target_key = NodeKey::new(target_level, target);
+        let node_key = NodeKey::new(target_level.saturating_sub(levels_below), node);
+        prop_assert!(!target_key.can_subdivide(node_key, detail));
+    }
+
+    /// The grown bounds of a node contain the ones of its children, so the refined region never has
+    /// holes.
+    #[test]
+    fn parent_of_subdivided_child_is_subdivided(
+        (target, detail, node_key) in target_detail_and_node(),
+        child in 0..8,
+    ) {
+        prop_assume!(node_key.level > target.level);
+        let offset = IVec3::new(child & 1, (child >> 1) & 1, (child >> 2) & 1);
+        let child_key = NodeKey::new(node_key.level - 1, (node_key.coordinates << 1) + offset);
+        if target.can_subdivide(child_key, detail) {
+            prop_assert!(
+                target.can_subdivide(node_key, detail),
+                "{:?} can subdivide but its parent {:?} can't",
+                child_key,
+                node_key
+            );
+        }
+    }
+
+    /// Whether a node of the refined tree is split only depends on the node itself: not on its
+    /// siblings, its neighbors, or the nodes refined around another target far away.
+    #[test]
+    fn refined_nodes_only_depend_on_themselves(
+        height in 2..=MAX_REFINED_HEIGHT,
+        detail in 0..=3,
+        position in prop::array::uniform3(-2.0f32..2.0),
+        far_axis in 0..3usize,
+        far_roots in prop_oneof![-5..=-3, 3..=5],
+    ) {
+        let root_length = (1 << (height - 1)) as f32;
+        let observer = LodObserver {
+            position: Vec3::from_array(position) * root_length,
+            detail,
+            min_level: 0,
+            projection_scale: None,
+        };
+        let target = observer.key();
+
+        let tree = refine_tree(height, &[observer]);
+        for key in tree.split_nodes() {
+            prop_assert!(key.level > 0 && target.can_subdivide(key, detail), "{:?} is split", key);
+        }
+        for key in tree.leaves() {
+            prop_assert!(key.level == 0 || !target.can_subdivide(key, detail), "{:?} is a leaf", key);
+        }
+
+        let mut far_position = observer.position;
+        far_position[far_axis] += far_roots as f32 * root_length;
+        let far_observer = LodObserver {
+            position: far_position,
+            ..observer
+        };
+        let both_tree = refine_tree(height, &[observer, far_observer]);
+
+        let root_level = height - 1;
+        let root_key = NodeKey::new(root_level, target.coordinates >> root_level as i32);
+        prop_assert_eq!(nodes_under(&tree, root_key), nodes_under(&both_tree, root_key));
+    }
+}
+
+/// The farthest targets from the nodes of the root level of the tallest tree, where the shifts are
+/// the largest.
+#[test]
+fn no_overflow_at_max_height() {
+    let root_level = MAX_OCTREE_HEIGHT - 1;
+    let extremes = [-MAX_COORDINATE, -1, 0, MAX_COORDINATE - 1];
+    for detail in [0, 1, MAX_DETAIL] {
+        for x in extremes {
+            for y in extremes {
+                for z in extremes {
+                    let target = NodeKey::new(0, IVec3::new(x, y, z));
+                    for offset in -2..=2 {
+                        let node_key = NodeKey::new(
+                            root_level,
+                            (target.coordinates >> root_level as i32) + offset,
+                        );
+                        assert_eq!(
+                            target.can_subdivide(node_key, detail),
+                            reference(target, node_key, detail),
+                            "target {target:?}, node {node_key:?}, detail {detail}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The greatest detail of each height still gives the reference result around the roots, and
+/// anything past it is rejected, by the settings as well.
+#[test]
+fn max_detail_of_every_height() {
+    for height in 1..=MAX_OCTREE_HEIGHT {
+        let root_level = height - 1;
+        let max_detail = max_detail(height).unwrap();
+        for x in [-1, 0, 1 << root_level] {
+            let target = NodeKey::new(0, IVec3::new(x, 0, 0));
+            for offset in -1..=1 {
+                let node_key = NodeKey::new(
+                    root_level,
+                    (target.coordinates >> root_level as i32) + offset,
+                );
+                assert_eq!(
+                    target.can_subdivide(node_key, max_detail),
+                    reference(target, node_key, max_detail),
+                    "target {target:?}, node {node_key:?}, detail {max_detail}"
+                );
+            }
+        }
+
+        for detail in [-1, max_detail + 1] {
+            assert_eq!(
+                check_lod_range(height, detail),
+                Err(LodRangeError::UnsupportedDetail {
+                    detail,
+                    height,
+                    max_detail,
+                })
+            );
+        }
+        let settings = OctreeSettings {
+            height,
+            detail: max_detail,
+            merge_margin: 1,
+            ..default()
+        };
+        assert!(settings.check_lod_range().is_err());
+    }
+
+    for height in [0, MAX_OCTREE_HEIGHT + 1] {
+        let error = LodRangeError::UnsupportedHeight {
+            height,
+            max_height: MAX_OCTREE_HEIGHT,
+        };
+        assert_eq!(max_detail(height), Err(error));
+        let settings = OctreeSettings {
+            height,
+            ..default()
+        };
+        assert_eq!(settings.check_lod_range(), Err(error));
+    }
+}
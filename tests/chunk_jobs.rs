@@ -0,0 +1,224 @@
+//! Drives the chunk entities through the plugin. Most tests split a leaf whose chunk has a finished
+//! job that wasn't polled yet, so that the chunk is despawned in the frame its job is handled.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy, transform::TransformPlugin, utils::Instant};
+use grid_tree::{Level, NodeKey};
+use octree_experiment::{
+    load::{ChunkState, LoadBudget},
+    voxel::{DensityGenerator, Sphere},
+    ChunkGenerator, ChunkKey, ChunkMaterial, LodNode, LodNodeAdded, LodNodeMerged, Octree,
+    OctreeLodPlugin, OctreeSettings, Target,
+};
+
+const HEIGHT: Level = 4;
+const LOD_TIMESTEP: f32 = 0.01;
+
+/// A sphere whose sampling blocks until the gate is open, to hold the jobs in their state.
+struct GatedSphere {
+    sphere: Sphere,
+    open: Arc<AtomicBool>,
+}
+
+impl DensityGenerator for GatedSphere {
+    fn density(&self, position: Vec3) -> f32 {
+        while !self.open.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+        self.sphere.density(position)
+    }
+}
+
+struct TestApp {
+    app: App,
+    gate: Arc<AtomicBool>,
+    timestep: Duration,
+    epoch: Instant,
+    ticks: u32,
+}
+
+impl TestApp {
+    fn new(meshing: bool) -> Self {
+        let root_length = (1 << (HEIGHT - 1)) as f32;
+        let gate = Arc::new(AtomicBool::new(false));
+        // The same period as the one `OctreeLodPlugin` derives from the settings.
+        let timestep = Duration::from_secs_f32(LOD_TIMESTEP);
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .insert_resource(OctreeSettings {
+                height: HEIGHT,
+                lod_timestep: LOD_TIMESTEP,
+                background_update: false,
+                ..default()
+            })
+            .insert_resource(LoadBudget {
+                jobs_per_frame: usize::MAX,
+                millis_per_frame: f32::INFINITY,
+                ..default()
+            })
+            .add_plugin(OctreeLodPlugin::<()>::default())
+            .insert_resource(FixedTime::new(timestep))
+            .insert_resource(ChunkGenerator(Arc::new(GatedSphere {
+                sphere: Sphere {
+                    center: Vec3::splat(root_length / 2.0),
+                    radius: root_length / 3.0,
+                },
+                open: gate.clone(),
+            })));
+        if meshing {
+            app.add_plugin(AssetPlugin::default())
+                .add_asset::<Mesh>()
+                .insert_resource(ChunkMaterial(Handle::default()));
+        }
+        app.world.spawn((
+            Target,
+            TransformBundle::from_transform(Transform::from_translation(Vec3::splat(0.5))),
+        ));
+
+        Self {
+            app,
+            gate,
+            timestep,
+            epoch: Instant::now(),
+            ticks: 0,
+        }
+    }
+
+    /// Runs one update, refining the tree once.
+    fn tick(&mut self) {
+        self.app.insert_resource(TimeUpdateStrategy::ManualInstant(
+            self.epoch + self.timestep * self.ticks,
+        ));
+        self.app.update();
+        self.ticks += 1;
+    }
+
+    fn set_gate(&self, open: bool) {
+        self.gate.store(open, Ordering::Release);
+    }
+
+    /// Opens the gate and waits until no job is running anymore, without polling them.
+    fn finish_jobs(&mut self) {
+        self.set_gate(true);
+        let start = Instant::now();
+        let mut states = self.app.world.query::<&ChunkState>();
+        while states.iter(&self.app.world).any(|state| match state {
+            ChunkState::Generating(task) => !task.is_finished(),
+            ChunkState::Meshing(task) => !task.is_finished(),
+            ChunkState::Queued | ChunkState::Ready => false,
+        }) {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "the jobs never finished"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn state(&mut self, key: NodeKey<IVec3>) -> Option<&ChunkState> {
+        self.app
+            .world
+            .query::<(&ChunkKey, &ChunkState)>()
+            .iter(&self.app.world)
+            .find(|(chunk, _)| chunk.key() == key)
+            .map(|(_, state)| state)
+    }
+
+    /// Moves the target to the far corner of the root, which splits the leaf covering it.
+    fn split(&mut self, key: NodeKey<IVec3>) {
+        let root_length = (1 << (HEIGHT - 1)) as f32;
+        let position = Vec3::splat(root_length - 0.5);
+        let mut target = self
+            .app
+            .world
+            .query_filtered::<(&mut Transform, &mut GlobalTransform), With<Target>>();
+        let (mut transform, mut global_transform) = target.single_mut(&mut self.app.world);
+        transform.translation = position;
+        *global_transform = GlobalTransform::from_translation(position);
+
+        self.tick();
+        assert!(!self.app.world.resource::<Octree>().is_leaf(key));
+        assert!(self.state(key).is_none());
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        // Lets the jobs of the despawned chunks return.
+        self.set_gate(true);
+    }
+}
+
+/// The leaf covering the far half of the root, coarse while the target is at the origin.
+fn far_leaf() -> NodeKey<IVec3> {
+    NodeKey::new(HEIGHT - 2, IVec3::ONE)
+}
+
+#[test]
+fn split_while_generating() {
+    let mut app = TestApp::new(false);
+    // Starts the clock, refines the tree, then starts the jobs of the new chunks.
+    for _ in 0..3 {
+        app.tick();
+    }
+    assert!(matches!(
+        app.state(far_leaf()),
+        Some(ChunkState::Generating(_))
+    ));
+
+    app.finish_jobs();
+    app.split(far_leaf());
+}
+
+#[test]
+fn split_while_meshing() {
+    let mut app = TestApp::new(true);
+    for _ in 0..3 {
+        app.tick();
+    }
+
+    app.finish_jobs();
+    app.set_gate(false);
+    app.tick();
+    assert!(matches!(
+        app.state(far_leaf()),
+        Some(ChunkState::Meshing(_))
+    ));
+
+    app.finish_jobs();
+    app.split(far_leaf());
+}
+
+/// Several fixed updates in one frame can add or merge the same key twice.
+#[test]
+fn repeated_leaf_events_spawn_one_chunk() {
+    let mut app = TestApp::new(false);
+    for _ in 0..3 {
+        app.tick();
+    }
+    let node = LodNode::new(far_leaf());
+    assert!(app.state(far_leaf()).is_some());
+
+    app.app.world.send_event(LodNodeAdded(node));
+    app.app.world.send_event(LodNodeMerged(node));
+    app.tick();
+
+    let chunks = app
+        .app
+        .world
+        .query::<&ChunkKey>()
+        .iter(&app.app.world)
+        .filter(|chunk| chunk.key() == far_leaf())
+        .count();
+    assert_eq!(chunks, 1);
+}
@@ -0,0 +1,89 @@
+//! The fixed targets the tree is refined around by several tests.
+
+use bevy::prelude::*;
+use grid_tree::Level;
+use octree_experiment::{
+    balance::Adjacency,
+    octree::Octree,
+    refine::{refine, RefineSettings},
+    subdivision::{ChebyshevBox, LodObserver},
+};
+
+pub struct Case {
+    pub name: &'static str,
+    pub height: Level,
+    pub detail: i32,
+    pub targets: &'static [[f32; 3]],
+}
+
+pub const CASES: &[Case] = &[
+    Case {
+        name: "origin",
+        height: 4,
+        detail: 1,
+        targets: &[[0.5, 0.5, 0.5]],
+    },
+    Case {
+        name: "root_corner",
+        height: 5,
+        detail: 1,
+        targets: &[[15.9, 0.1, 15.9]],
+    },
+    Case {
+        name: "negative",
+        height: 5,
+        detail: 1,
+        targets: &[[-3.25, -0.5, -7.75]],
+    },
+    Case {
+        name: "root_boundary",
+        height: 5,
+        detail: 2,
+        targets: &[[16.0, 0.0, -16.0]],
+    },
+    Case {
+        name: "wide_detail",
+        height: 4,
+        detail: 3,
+        targets: &[[5.5, 2.5, 9.5]],
+    },
+    Case {
+        name: "deep",
+        height: 7,
+        detail: 1,
+        targets: &[[37.5, -12.25, 3.0]],
+    },
+    Case {
+        name: "two_targets",
+        height: 5,
+        detail: 1,
+        targets: &[[2.5, 2.5, 2.5], [-20.5, 4.5, 9.5]],
+    },
+];
+
+pub fn observers(case: &Case) -> Vec<LodObserver> {
+    case.targets
+        .iter()
+        .map(|&position| LodObserver {
+            position: Vec3::from_array(position),
+            detail: case.detail,
+            min_level: 0,
+            projection_scale: None,
+        })
+        .collect()
+}
+
+pub fn refined_tree(case: &Case, balance: Option<Adjacency>) -> Octree {
+    let mut tree = Octree::new(case.height);
+    refine(
+        &mut tree,
+        &observers(case),
+        &ChebyshevBox,
+        RefineSettings {
+            height: case.height,
+            merge_margin: 0,
+            balance,
+        },
+    );
+    tree
+}
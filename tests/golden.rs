@@ -0,0 +1,125 @@
+//! Compares the nodes produced by refining the tree around fixed targets with the golden files in
+//! `tests/golden`. After an intended change of the LOD policy, regenerate them with
+//! `BLESS=1 cargo test --test golden` and review the diff.
+
+mod common;
+
+use std::{env, fmt::Write, fs, path::PathBuf};
+
+use bevy::utils::HashSet;
+use common::{observers, refined_tree, Case, CASES};
+use octree_experiment::octree_i64::{root_keys, LodObserverI64, NodeKeyI64};
+
+/// Every node of the tree refined for `case`, one per line, sorted by level then coordinates.
+fn snapshot(case: &Case) -> String {
+    let tree = refined_tree(case, None);
+    let mut nodes: Vec<_> = tree
+        .leaves()
+        .map(|key| (key, "leaf"))
+        .chain(tree.split_nodes().into_iter().map(|key| (key, "split")))
+        .map(|(key, kind)| {
+            let [x, y, z] = key.coordinates.to_array();
+            (key.level, x, y, z, kind)
+        })
+        .collect();
+    nodes.sort();
+
+    let mut out = format!(
+        "# height {}, detail {}, {} target(s)\n",
+        case.height,
+        case.detail,
+        case.targets.len()
+    );
+    for (level, x, y, z, kind) in nodes {
+        writeln!(out, "{level} {x} {y} {z} {kind}").unwrap();
+    }
+    out
+}
+
+fn golden_path(case: &Case) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(case.name)
+        .with_extension("txt")
+}
+
+#[test]
+fn node_sets_match_golden_files() {
+    let bless = env::var_os("BLESS").is_some();
+
+    let mut mismatches = Vec::new();
+    for case in CASES {
+        let actual = snapshot(case);
+        let path = golden_path(case);
+
+        if bless {
+            fs::write(&path, &actual).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(&path).unwrap_or_else(|error| {
+            panic!(
+                "can't read {}: {error}, run with BLESS=1 to create it",
+                path.display()
+            )
+        });
+        if actual == expected {
+            continue;
+        }
+
+        let (line, expected_line, actual_line) = expected
+            .lines()
+            .map(Some)
+            .chain(std::iter::repeat(None))
+            .zip(actual.lines().map(Some).chain(std::iter::repeat(None)))
+            .enumerate()
+            .find(|(_, (expected, actual))| expected != actual)
+            .map(|(i, (expected, actual))| (i + 1, expected, actual))
+            .expect("the snapshots differ");
+        mismatches.push(format!(
+            "{}: line {line}: expected {:?}, got {:?}",
+            case.name,
+            expected_line.unwrap_or("<end of file>"),
+            actual_line.unwrap_or("<end of file>"),
+        ));
+    }
+
+    assert!(
+        mismatches.is_empty(),
+        "the LOD node sets changed, run with BLESS=1 if that is intended:\n{}",
+        mismatches.join("\n")
+    );
+}
+
+/// The golden files only record what the tree did, so check separately that its roots are the
+/// ones the `i64` search finds, which `tests/octree_i64.rs` checks against every nearby root.
+#[test]
+fn roots_match_i64_search() {
+    for case in CASES {
+        let tree = refined_tree(case, None);
+        let roots: HashSet<_> = tree
+            .iter_roots()
+            .map(|(root_key, _)| {
+                NodeKeyI64::new(
+                    root_key.level,
+                    root_key.coordinates.to_array().map(i64::from),
+                )
+            })
+            .collect();
+
+        let observers: Vec<_> = observers(case)
+            .iter()
+            .map(|observer| LodObserverI64 {
+                position: observer.position.as_dvec3(),
+                detail: observer.detail,
+                min_level: observer.min_level,
+            })
+            .collect();
+        assert_eq!(
+            Ok(roots),
+            root_keys(case.height, &observers),
+            "{}",
+            case.name
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::{math::DVec3, prelude::*};
+use grid_tree::{Level, NodeKey};
+use octree_experiment::{
+    octree_i64::{check_lod_range, max_detail, root_keys, LodObserverI64, NodeKeyI64},
+    subdivision::CanSubdivide,
+    LodRangeError, MAX_OCTREE_HEIGHT, MAX_OCTREE_HEIGHT_I64,
+};
+use proptest::prelude::*;
+
+fn to_i64(key: NodeKey<IVec3>) -> NodeKeyI64 {
+    NodeKeyI64::new(key.level, key.coordinates.to_array().map(i64::from))
+}
+
+fn observer_at(position: DVec3, detail: i32) -> LodObserverI64 {
+    LodObserverI64 {
+        position,
+        detail,
+        min_level: 0,
+    }
+}
+
+proptest! {
+    /// Within the heights the `i32` tree supports, both paths agree.
+    #[test]
+    fn matches_i32_path(
+        target in prop::array::uniform3(-(1 << 20)..1 << 20),
+        detail in 0..=16,
+        node_level in 0..MAX_OCTREE_HEIGHT,
+        offset in prop::array::uniform3(-10..=10),
+    ) {
+        let target = NodeKey::new(0, IVec3::from_array(target));
+        let node_key = NodeKey::new(
+            node_level,
+            (target.coordinates >> node_level as i32) + IVec3::from_array(offset),
+        );
+        prop_assert_eq!(
+            to_i64(target).checked_can_subdivide(to_i64(node_key), detail),
+            Ok(target.can_subdivide(node_key, detail))
+        );
+    }
+}
+
+#[test]
+fn rejects_unsupported_heights() {
+    for height in [0, MAX_OCTREE_HEIGHT_I64 + 1, Level::MAX] {
+        let error = LodRangeError::UnsupportedHeight {
+            height,
+            max_height: MAX_OCTREE_HEIGHT_I64,
+        };
+        assert_eq!(max_detail(height), Err(error));
+        assert_eq!(
+            root_keys(height, &[observer_at(DVec3::ZERO, 1)]),
+            Err(error)
+        );
+    }
+}
+
+#[test]
+fn rejects_unsupported_details() {
+    for height in [1, 24, 40, MAX_OCTREE_HEIGHT_I64] {
+        let max_detail = max_detail(height).unwrap();
+        assert!(
+            max_detail > 1000,
+            "height {height} only supports detail {max_detail}"
+        );
+        assert_eq!(check_lod_range(height, max_detail), Ok(()));
+
+        for detail in [Some(-1), max_detail.checked_add(1)].into_iter().flatten() {
+            let error = LodRangeError::UnsupportedDetail {
+                detail,
+                height,
+                max_detail,
+            };
+            assert_eq!(check_lod_range(height, detail), Err(error));
+
+            assert_eq!(
+                root_keys(height, &[observer_at(DVec3::ZERO, detail)]),
+                Err(error)
+            );
+        }
+    }
+}
+
+#[test]
+fn rejects_observers_out_of_range() {
+    for position in [DVec3::NAN, DVec3::splat(f64::INFINITY), DVec3::splat(1e18)] {
+        // Compared bit for bit, since NaN isn't equal to itself.
+        let result = root_keys(40, &[observer_at(position, 1)]);
+        assert!(
+            matches!(
+                result,
+                Err(LodRangeError::ObserverOutOfRange { position: rejected })
+                    if rejected.to_array().map(f64::to_bits) == position.to_array().map(f64::to_bits)
+            ),
+            "{result:?}"
+        );
+    }
+}
+
+#[test]
+fn reports_overflow() {
+    let target = NodeKeyI64::new(0, [i64::MAX / 2, 0, 0]);
+    let node_key = NodeKeyI64::new(0, [i64::MAX / 2, 0, 0]);
+    assert!(matches!(
+        target.checked_can_subdivide(node_key, 1),
+        Err(LodRangeError::Overflow { .. })
+    ));
+}
+
+#[test]
+fn finds_every_subdivided_root() {
+    for height in [24, 32, 40, MAX_OCTREE_HEIGHT_I64] {
+        let root_level = height - 1;
+        let root_length = (1i64 << root_level) as f64;
+        let position = DVec3::new(root_length * 3.5, -root_length * 0.25, 12345.5);
+
+        for detail in [0, 1, 4, 7] {
+            let observer = observer_at(position, detail);
+            let roots = root_keys(height, &[observer]).unwrap();
+
+            // The bounds of the roots grow by `detail / 2` root widths on each side, so the roots
+            // the observer subdivides are all within this many roots of its own.
+            let key = observer.key().unwrap();
+            let observer_root = key.ancestor(root_level);
+            let reach = i64::from(detail / 2 + 2);
+            let mut expected = vec![observer_root];
+            for x in -reach..=reach {
+                for y in -reach..=reach {
+                    for z in -reach..=reach {
+                        let [r_x, r_y, r_z] = observer_root.coordinates;
+                        let root_key = NodeKeyI64::new(root_level, [r_x + x, r_y + y, r_z + z]);
+                        if root_key != observer_root && observer.can_subdivide(root_key).unwrap() {
+                            expected.push(root_key);
+                        }
+                    }
+                }
+            }
+            assert_eq!(
+                roots,
+                expected.into_iter().collect(),
+                "height {height}, detail {detail}"
+            );
+
+            // The observer ends up in a level 0 leaf, whatever the detail.
+            for level in 1..height {
+                assert!(observer.can_subdivide(key.ancestor(level)).unwrap());
+            }
+        }
+    }
+}
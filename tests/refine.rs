@@ -0,0 +1,191 @@
+//! Checks that refining a tree again only revisits the regions around the observers that moved,
+//! and that it ends up with the same nodes as testing the whole tree again, on the main thread or
+//! through the LOD worker.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, TaskPool},
+    utils::HashSet,
+};
+use grid_tree::{Level, NodeKey};
+use octree_experiment::{
+    octree::{NodePayload, Octree},
+    refine::{refine, LodWorker, RefineSettings},
+    subdivision::{ChebyshevBox, LodObserver, SubdivisionStrategy},
+};
+
+const HEIGHT: Level = 8;
+
+/// A payload numbering the nodes in the order they are created.
+struct Serial(usize);
+
+impl NodePayload for Serial {
+    fn create(_key: NodeKey<IVec3>) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// [`ChebyshevBox`], recording the nodes it is asked about.
+#[derive(Default)]
+struct Recording {
+    tested: Mutex<HashSet<NodeKey<IVec3>>>,
+}
+
+impl SubdivisionStrategy for Recording {
+    fn can_subdivide(&self, observer: &LodObserver, node_key: NodeKey<IVec3>) -> bool {
+        self.tested.lock().unwrap().insert(node_key);
+        ChebyshevBox.can_subdivide(observer, node_key)
+    }
+}
+
+fn observer(position: Vec3) -> LodObserver {
+    LodObserver {
+        position,
+        detail: 1,
+        min_level: 0,
+        projection_scale: None,
+    }
+}
+
+fn settings(merge_margin: i32) -> RefineSettings {
+    RefineSettings {
+        height: HEIGHT,
+        merge_margin,
+        balance: None,
+    }
+}
+
+/// Whether `key` is `ancestor` or one of its descendants.
+fn is_within(key: NodeKey<IVec3>, ancestor: NodeKey<IVec3>) -> bool {
+    key.level <= ancestor.level
+        && key.coordinates >> (ancestor.level - key.level) as i32 == ancestor.coordinates
+}
+
+/// The nodes within `ancestor`, with the serial number of their payload.
+fn serials(tree: &Octree<Serial>, ancestor: NodeKey<IVec3>) -> Vec<(Level, IVec3, usize)> {
+    let mut serials: Vec<_> = tree
+        .leaves()
+        .chain(tree.split_nodes())
+        .filter(|&key| is_within(key, ancestor))
+        .map(|key| (key.level, key.coordinates, tree.payload(key).unwrap().0))
+        .collect();
+    serials.sort_by_key(|&(level, coordinates, _)| (level, coordinates.to_array()));
+    serials
+}
+
+#[test]
+fn untouched_subtrees_are_kept() {
+    let still = observer(Vec3::splat(10.5));
+    let moving = observer(Vec3::new(100.5, 20.5, 60.5));
+    // A node around the still observer, which the moving one never subdivides, even with the
+    // merge margin.
+    let region = NodeKey::new(HEIGHT - 3, IVec3::ZERO);
+
+    let strategy = Recording::default();
+    let mut tree = Octree::<Serial>::new(HEIGHT);
+    refine(&mut tree, &[still, moving], &strategy, settings(1));
+    let before = serials(&tree, region);
+    assert!(before.iter().any(|&(level, ..)| level == 0));
+
+    strategy.tested.lock().unwrap().clear();
+    let moved = observer(moving.position + Vec3::X);
+    let changes = refine(&mut tree, &[still, moved], &strategy, settings(1));
+    assert!(!changes.is_empty());
+
+    let tested = strategy.tested.lock().unwrap();
+    let visited: Vec<_> = tested
+        .iter()
+        .filter(|&&key| key != region && is_within(key, region))
+        .collect();
+    assert!(visited.is_empty(), "visited {visited:?}");
+    assert_eq!(serials(&tree, region), before);
+}
+
+/// Refining again after each move gives the same nodes as forgetting the last refinement first,
+/// merge margin included.
+#[test]
+fn matches_full_refinement() {
+    let root_length = (1 << (HEIGHT - 1)) as f32;
+    let mut incremental = Octree::<()>::new(HEIGHT);
+    let mut full = Octree::<()>::new(HEIGHT);
+
+    for merge_margin in [0, 2] {
+        for step in 0..64 {
+            let angle = step as f32 / 8.0;
+            let observers = [
+                observer(
+                    Vec3::splat(root_length / 2.0)
+                        + Vec3::new(angle.cos(), 0.1, angle.sin()) * 30.0,
+                ),
+                observer(Vec3::new(5.5 + step as f32, 3.25, -7.5)),
+            ];
+            refine(
+                &mut incremental,
+                &observers,
+                &ChebyshevBox,
+                settings(merge_margin),
+            );
+            full.forget_refinement();
+            refine(&mut full, &observers, &ChebyshevBox, settings(merge_margin));
+
+            let leaves = |tree: &Octree| tree.leaves().collect::<HashSet<_>>();
+            assert_eq!(
+                leaves(&incremental),
+                leaves(&full),
+                "margin {merge_margin}, step {step}"
+            );
+        }
+    }
+}
+
+/// The worker makes the same changes as refining on the main thread, without recreating the
+/// payloads of the nodes that stay, including across a change of height and a direct refinement.
+#[test]
+fn worker_matches_main_thread() {
+    AsyncComputeTaskPool::init(TaskPool::new);
+    let mut worker = LodWorker::default();
+    let mut front = Octree::<Serial>::new(HEIGHT);
+    let mut reference = Octree::<()>::new(HEIGHT);
+
+    for step in 0..24 {
+        let height = if step < 12 { HEIGHT } else { HEIGHT - 1 };
+        let settings = RefineSettings {
+            height,
+            merge_margin: 1,
+            balance: None,
+        };
+        let observers = [
+            observer(Vec3::new(40.5 + 3.0 * step as f32, 8.5, 90.5)),
+            observer(Vec3::splat(10.5)),
+        ];
+        let region = NodeKey::new(height - 3, IVec3::ZERO);
+        let before = serials(&front, region);
+
+        if step == 6 {
+            refine(&mut front, &observers, &ChebyshevBox, settings);
+            worker.mark_stale();
+        } else {
+            worker.start(&front, observers.to_vec(), Arc::new(ChebyshevBox), settings);
+            while worker.poll(&mut front).is_none() {}
+        }
+        refine(&mut reference, &observers, &ChebyshevBox, settings);
+
+        assert_eq!(front.root_level(), reference.root_level(), "step {step}");
+        assert_eq!(
+            front.leaves().collect::<HashSet<_>>(),
+            reference.leaves().collect::<HashSet<_>>(),
+            "step {step}"
+        );
+        assert_eq!(front.split_nodes(), reference.split_nodes(), "step {step}");
+        // The first step and the change of height build the tree from scratch.
+        if step != 0 && step != 12 {
+            assert_eq!(serials(&front, region), before, "step {step}");
+        }
+    }
+}
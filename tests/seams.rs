@@ -0,0 +1,110 @@
+//! Meshes every leaf of a tree spanning several levels and checks that the chunk meshes join into
+//! closed surfaces, including where a surface is too small for the coarser chunk to see it.
+
+use bevy::{prelude::*, utils::HashMap};
+use grid_tree::Level;
+use octree_experiment::{
+    mesh::{surface_nets, ChunkNeighborhood},
+    octree::{find_leaf, Octree},
+    refine::{refine, RefineSettings},
+    subdivision::{ChebyshevBox, LodObserver},
+    voxel::{ChunkVoxels, Sphere},
+};
+
+const HEIGHT: Level = 5;
+
+fn refined_tree() -> Octree {
+    let mut tree = Octree::new(HEIGHT);
+    let observer = LodObserver {
+        position: Vec3::splat(3.3),
+        detail: 1,
+        min_level: 0,
+        projection_scale: None,
+    };
+    refine(
+        &mut tree,
+        &[observer],
+        &ChebyshevBox,
+        RefineSettings {
+            height: HEIGHT,
+            merge_margin: 0,
+            balance: None,
+        },
+    );
+    tree
+}
+
+/// Counts the edges of the meshes of all the leaves that aren't shared by two triangles facing the
+/// same way. Vertices are welded by their world position.
+fn open_edges(tree: &Octree, sphere: &Sphere) -> usize {
+    let leaves = tree.leaves_snapshot();
+    let leaf_at = |position| find_leaf(&leaves, tree.root_level(), position);
+
+    let mut vertex_ids = HashMap::<[i32; 3], usize>::default();
+    // +1 for each edge going from the lower id to the higher one, -1 for the other way around.
+    let mut edges = HashMap::<(usize, usize), i32>::default();
+    for key in tree.leaves() {
+        let voxels = ChunkVoxels::sample(sphere, key);
+        let neighborhood = ChunkNeighborhood {
+            key,
+            leaf_at: &leaf_at,
+            generator: sphere,
+        };
+        let buffer = surface_nets(&voxels, &neighborhood);
+
+        let chunk_min = (key.coordinates * (1 << key.level)).as_vec3();
+        let ids: Vec<_> = buffer
+            .positions
+            .iter()
+            .map(|&position| {
+                let welded = ((chunk_min + Vec3::from(position)) * 1000.0).round();
+                let next_id = vertex_ids.len();
+                *vertex_ids
+                    .entry(welded.as_ivec3().to_array())
+                    .or_insert(next_id)
+            })
+            .collect();
+
+        for triangle in buffer.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| ids[triangle[i] as usize]);
+            if a == b || b == c || c == a {
+                continue;
+            }
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                *edges.entry((from.min(to), from.max(to))).or_default() +=
+                    if from < to { 1 } else { -1 };
+            }
+        }
+    }
+    edges.values().filter(|&&count| count != 0).count()
+}
+
+#[test]
+fn seams_are_closed() {
+    let tree = refined_tree();
+    let levels: Vec<_> = (0..HEIGHT)
+        .filter(|&level| tree.leaves().any(|leaf| leaf.level == level))
+        .collect();
+    assert_eq!(levels, [0, 1, 2, 3]);
+
+    let spheres = [
+        // Crosses leaves of every level.
+        Sphere {
+            center: Vec3::splat(8.0),
+            radius: 5.3,
+        },
+        // Only the finer chunks see it where it crosses a level change.
+        Sphere {
+            center: Vec3::new(7.84, 7.52, 6.24),
+            radius: 0.286,
+        },
+    ];
+    for sphere in spheres {
+        assert_eq!(
+            open_edges(&tree, &sphere),
+            0,
+            "the mesh of the sphere at {} is open",
+            sphere.center
+        );
+    }
+}